@@ -0,0 +1,164 @@
+//! A ready-made async runner for a resolved `Dependy` graph.
+//!
+//! Enabled with the `tokio` feature.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::panic;
+
+use tokio::task::JoinSet;
+
+use super::Dependy;
+
+/// Run every dependency from the most recent resolution of `depgraph`.
+///
+/// Each dependency is handed to `run` once all of its parents have finished,
+/// with at most `concurrency` of them in flight at a time.  The outcome of
+/// each one is recorded with `mark_successful` or `mark_failure`, and
+/// anything that requires a failed dependency is never started.
+///
+/// Returns the dependencies that were skipped because of a failure.
+pub async fn execute<K, F, Fut>(depgraph: &mut Dependy<K>, concurrency: usize, run: F) -> Vec<K>
+where
+    K: Clone + Eq + Hash + fmt::Display + Send + 'static,
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    let concurrency = concurrency.max(1);
+    let mut in_flight = HashSet::new();
+    let mut tasks = JoinSet::new();
+
+    loop {
+        for name in depgraph.next_runnable() {
+            if tasks.len() >= concurrency {
+                break;
+            }
+            if !in_flight.insert(name.clone()) {
+                continue;
+            }
+            let task = run(name.clone());
+            tasks.spawn(async move { (name, task.await) });
+        }
+
+        match tasks.join_next().await {
+            None => break,
+            Some(Ok((name, true))) => {
+                in_flight.remove(&name);
+                depgraph.mark_successful(&name);
+            }
+            Some(Ok((name, false))) => {
+                in_flight.remove(&name);
+                depgraph.mark_failure(&name);
+            }
+            Some(Err(e)) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    depgraph.skipped_dependencies()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dependency;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct TestDep {
+        name: String,
+        requirements: Vec<String>,
+        suggestions: Vec<String>,
+        none: Vec<String>,
+    }
+    impl TestDep {
+        fn new(name: &str, requirements: &[&str]) -> TestDep {
+            TestDep {
+                name: name.to_owned(),
+                requirements: requirements.iter().map(|r| r.to_string()).collect(),
+                suggestions: vec![],
+                none: vec![],
+            }
+        }
+
+        fn suggesting(mut self, suggestions: &[&str]) -> TestDep {
+            self.suggestions = suggestions.iter().map(|s| s.to_string()).collect();
+            self
+        }
+    }
+    impl Dependency<String> for TestDep {
+        fn name(&self) -> &String {
+            &self.name
+        }
+        fn requirements(&self) -> &Vec<String> {
+            &self.requirements
+        }
+        fn suggestions(&self) -> &Vec<String> {
+            &self.suggestions
+        }
+        fn provides(&self) -> &Vec<String> {
+            &self.none
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_dependents_of_failures() {
+        let mut depgraph = Dependy::new();
+        depgraph.add_dependency(&TestDep::new("setup", &[]));
+        depgraph.add_dependency(&TestDep::new("broken", &["setup"]));
+        depgraph.add_dependency(&TestDep::new("after-broken", &["broken"]));
+        depgraph.add_dependency(&TestDep::new("independent", &["setup"]));
+        depgraph
+            .resolve_named_dependencies(&vec!["after-broken".to_string(),
+                                              "independent".to_string()])
+            .unwrap();
+
+        let skipped = execute(&mut depgraph, 2, |name| async move { name != "broken" }).await;
+        assert_eq!(skipped, vec!["after-broken".to_string()]);
+        assert!(depgraph.next_runnable().is_empty());
+    }
+
+    #[tokio::test]
+    async fn runs_suggesters_of_skipped() {
+        let mut depgraph = Dependy::new();
+        depgraph.add_dependency(&TestDep::new("broken", &[]));
+        depgraph.add_dependency(&TestDep::new("after-broken", &["broken"]));
+        depgraph.add_dependency(&TestDep::new("hopeful", &[]).suggesting(&["after-broken"]));
+        depgraph.resolve_named_dependencies(&vec!["hopeful".to_string()]).unwrap();
+
+        let skipped = execute(&mut depgraph, 2, |name| async move { name != "broken" }).await;
+        assert_eq!(skipped, vec!["after-broken".to_string()]);
+        assert_eq!(depgraph.results.get("hopeful"), Some(&true));
+    }
+
+    #[tokio::test]
+    async fn respects_concurrency() {
+        let mut depgraph = Dependy::new();
+        let names: Vec<String> = (0..6).map(|i| format!("task{}", i)).collect();
+        for name in &names {
+            depgraph.add_dependency(&TestDep::new(name, &[]));
+        }
+        depgraph.resolve_named_dependencies(&names).unwrap();
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let skipped = execute(&mut depgraph, 2, |_| {
+            let running = running.clone();
+            let most = most.clone();
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                running.fetch_sub(1, Ordering::SeqCst);
+                true
+            }
+        })
+        .await;
+        assert!(skipped.is_empty());
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert!(depgraph.next_runnable().is_empty());
+    }
+}
@@ -1,27 +1,80 @@
 extern crate daggy;
 extern crate petgraph;
-
-use self::daggy::{Dag, Walker, NodeIndex};
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+#[cfg(feature = "tokio")]
+pub mod executor;
+
+pub use self::daggy::{Dag, NodeIndex};
+use self::daggy::{EdgeIndex, Walker, WouldCycle};
+use petgraph::algo::kosaraju_scc;
 use petgraph::dot::Dot;
+use petgraph::Graph;
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::hash::Hash;
 use std::io::Write;
 use std::io;
+use std::mem;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum DepError<K> where K: Clone {
-    RequirementsNotFound(K),
-    RequirementNotFound(K, K),
-    SuggestionsNotFound(K),
-    SuggestionNotFound(K, K),
+    /// `requested` was found, but has no list of requirements.  `resolved`
+    /// is the dependency it refers to once aliases have been followed.
+    RequirementsNotFound { requested: K, resolved: K },
+
+    /// The dependency asked for as `requested`, declared as `resolved`,
+    /// requires `requirement`, which doesn't exist.
+    RequirementNotFound { requested: K, resolved: K, requirement: K },
+
+    /// `requested` was found, but has no list of suggestions.  `resolved`
+    /// is the dependency it refers to once aliases have been followed.
+    SuggestionsNotFound { requested: K, resolved: K },
+
+    /// The dependency asked for as `requested`, declared as `resolved`,
+    /// suggests `suggestion`, which doesn't exist.
+    SuggestionNotFound { requested: K, resolved: K, suggestion: K },
+
+    /// Nothing is registered under this name, either as a name or as an
+    /// alias.
     DependencyNotFound(K),
-    CircularDependency(K, K),
+
+    /// The dependency asked for as `requested`, declared as `resolved`,
+    /// can't come after `target`, because `target` already comes after it.
+    CircularDependency { requested: K, resolved: K, target: K },
+
+    /// The dependency asked for as `requested`, declared as `resolved`,
+    /// requires `requirement`, but that was excluded by the tags being
+    /// resolved with.
+    RequirementFiltered { requested: K, resolved: K, requirement: K },
+
+    /// Resolution was asked for with an empty list of roots, and the
+    /// resolver was configured to treat that as an error.
+    NoRootsProvided,
+
+    /// Resolution events could not be written out.
+    EventWriteFailed(io::Error),
+
+    /// Resolution took more than the number of steps allowed by
+    /// `with_max_iterations`, which is given here.
+    ResolutionLimitExceeded(usize),
+
+    /// `check_acyclic` found a cycle through these dependencies.  Edges
+    /// are checked as they are added, so this indicates a bug rather than a
+    /// problem with the declarations.
+    CycleDetected(Vec<K>),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DepEdge {
     /// Dependency B Requires dependency A, and a failure of A
     /// prevents B from running
@@ -43,19 +96,107 @@ impl fmt::Display for DepEdge {
     }
 }
 
+/// Where a dependency sits in the most recently resolved graph, ignoring
+/// `Follows` edges.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    /// Nothing requires or suggests it, but it has requirements of its own.
+    Root,
+
+    /// It requires and suggests nothing, but something depends on it.
+    Leaf,
+
+    /// Both a root and a leaf: it is connected to nothing else.
+    Both,
+
+    /// It has both requirements and dependents.
+    Internal,
+}
+
+/// How far along a run of the most recently resolved dependencies is.
+#[derive(Debug, PartialEq)]
+pub enum ResolutionState<K> {
+    /// Every dependency has a result.
+    Complete,
+
+    /// These dependencies are ready to run.
+    Ready(Vec<K>),
+
+    /// Nothing can run, and these dependencies never will because
+    /// something they require failed.
+    Blocked(Vec<K>),
+
+    /// Nothing can run, yet these dependencies have neither a result nor a
+    /// failed requirement.
+    Deadlocked(Vec<K>),
+}
+
+/// Something notable that happened while resolving dependencies.
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+enum ResolveEvent<'a, K: 'a> {
+    /// A node was placed into the final order.
+    Visited(&'a K),
+
+    /// An edge was added from the first node to the second.
+    EdgeAdded(&'a K, &'a K, DepEdge),
+
+    /// An edge was already present, so it wasn't added again.
+    EdgeExists(&'a K, &'a K, DepEdge),
+
+    /// A `Follows` edge was left out because it would have made a cycle.
+    CycleDropped(&'a K, &'a K),
+
+    /// The first node suggests the second, but the second was excluded.
+    SuggestionSkipped(&'a K, &'a K),
+}
+
+type TieBreakFn<K> = dyn Fn(&K, &K) -> Ordering + Send + Sync;
+
+/// A caller-supplied ordering between otherwise independent dependencies.
+#[derive(Clone)]
+struct TieBreaker<K>(Arc<TieBreakFn<K>>);
+impl<K> fmt::Debug for TieBreaker<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TieBreaker")
+    }
+}
+
+/// Settings that apply to a single resolution.
+#[derive(Default)]
+struct ResolveOptions<'a> {
+    /// Treat `Suggests` edges as `Requires` when deciding what may run.
+    promote_suggestions: bool,
+
+    /// Skip over missing dependencies and cycles instead of failing.
+    best_effort: bool,
+
+    /// Only include dependencies with one of the first set of tags (if it
+    /// isn't empty) and none of the second.
+    tag_filter: Option<(&'a [String], &'a [String])>,
+}
+
 pub trait Dependency<K> where K: Clone + Eq + Hash {
     fn name(&self) -> &K;
     fn requirements(&self) -> &Vec<K>;
     fn suggestions(&self) -> &Vec<K>;
     fn provides(&self) -> &Vec<K>;
+
+    /// How strongly this dependency wants to be the provider of
+    /// `capability`.  When several dependencies provide the same thing, the
+    /// one with the highest priority wins, and ties go to whichever was
+    /// added first.
+    fn provides_priority(&self, _capability: &K) -> i32 {
+        0
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct InternalDependency<K> where K: Clone + Eq + Hash {
     name: K,
     requirements: Vec<K>,
     suggestions: Vec<K>,
     provides: Vec<K>,
+    priorities: HashMap<K, i32>,
 }
 impl<K> Dependency<K> for InternalDependency<K> where K: Clone + Eq + Hash {
     fn name(&self) -> &K {
@@ -70,8 +211,40 @@ impl<K> Dependency<K> for InternalDependency<K> where K: Clone + Eq + Hash {
     fn provides(&self) -> &Vec<K> {
         &self.provides
     }
+    fn provides_priority(&self, capability: &K) -> i32 {
+        self.priorities.get(capability).cloned().unwrap_or(0)
+    }
 }
-#[derive(Debug)]
+
+/// A `(name, requirements, suggestions, provides)` tuple, for when
+/// defining a type isn't worth it.
+impl<K> Dependency<K> for (K, Vec<K>, Vec<K>, Vec<K>) where K: Clone + Eq + Hash {
+    fn name(&self) -> &K {
+        &self.0
+    }
+    fn requirements(&self) -> &Vec<K> {
+        &self.1
+    }
+    fn suggestions(&self) -> &Vec<K> {
+        &self.2
+    }
+    fn provides(&self) -> &Vec<K> {
+        &self.3
+    }
+}
+/// The edges and resolution state of a `Dependy` at some point, taken with
+/// `Dependy::snapshot`.
+#[derive(Clone, Debug)]
+pub struct Snapshot<K> {
+    generation: u64,
+    edge_count: usize,
+    resolved: Vec<K>,
+    ranks: HashMap<K, usize>,
+    promote_suggestions: bool,
+    duplicates: Vec<(K, K, DepEdge)>,
+}
+
+#[derive(Clone, Debug)]
 pub struct Dependy<K> where K: Clone + Eq + Hash {
     /// The graph structure, which we will iterate over.
     graph: Dag<K, DepEdge>,
@@ -85,11 +258,47 @@ pub struct Dependy<K> where K: Clone + Eq + Hash {
     /// A mapping of "provides" to actual names.
     provides_map: HashMap<K, K>,
 
+    /// The priority the current provider of each alias claimed it with.
+    provider_priority: HashMap<K, i32>,
+
     requirements: HashMap<K, Vec<K>>,
     suggestions: HashMap<K, Vec<K>>,
 
     /// Useed for testing, and making sure the graph is sane.
     dep_map: HashMap<K, InternalDependency<K>>,
+
+    /// Whether an empty root list is an error rather than an empty order.
+    require_roots: bool,
+
+    /// The order produced by the most recent resolution.
+    resolved: Vec<K>,
+
+    /// Whether the most recent resolution treats suggestions as requirements.
+    promote_suggestions: bool,
+
+    /// The position of each dependency in `resolved`.
+    ranks: HashMap<K, usize>,
+
+    /// Whether to order the roots by their position in the list.
+    follows: bool,
+
+    /// How to order dependencies that could otherwise go either way.
+    tie_breaker: Option<TieBreaker<K>>,
+
+    /// Names registered with `add_group`, which never appear in an order.
+    groups: HashSet<K>,
+
+    /// Edges declared more than once, found during the most recent resolution.
+    duplicates: Vec<(K, K, DepEdge)>,
+
+    /// Tags attached to each dependency with `add_tag`.
+    tags: HashMap<K, HashSet<String>>,
+
+    /// Bumped whenever edges are removed, which invalidates snapshots.
+    generation: u64,
+
+    /// How many dependencies a single resolution may expand, if limited.
+    max_iterations: Option<usize>,
 }
 
 impl<K> Dependy<K> where K: Clone + Eq + Hash + fmt::Display {
@@ -101,138 +310,782 @@ impl<K> Dependy<K> where K: Clone + Eq + Hash + fmt::Display {
             requirements: HashMap::new(),
             suggestions: HashMap::new(),
             provides_map: HashMap::new(),
+            provider_priority: HashMap::new(),
             dep_map: HashMap::new(),
+            require_roots: false,
+            resolved: vec![],
+            promote_suggestions: false,
+            ranks: HashMap::new(),
+            follows: true,
+            tie_breaker: None,
+            groups: HashSet::new(),
+            duplicates: vec![],
+            tags: HashMap::new(),
+            generation: 0,
+            max_iterations: None,
         }
     }
 
-    pub fn add_dependency<T: Dependency<K>>(&mut self, dependency: &T) {
+    /// Treat resolving an empty list of roots as `DepError::NoRootsProvided`
+    /// instead of silently returning an empty order.
+    pub fn with_require_roots(&mut self, require: bool) -> &mut Self {
+        self.require_roots = require;
+        self
+    }
+
+    /// Whether roots should run in the order they were listed, where their
+    /// own requirements allow it.  When disabled, no `Follows` edges are
+    /// added and the order of the root list carries no meaning.
+    pub fn with_follows(&mut self, follows: bool) -> &mut Self {
+        self.follows = follows;
+        self
+    }
+
+    /// Whenever more than one dependency could come next in the order, take
+    /// the one `cmp` puts first, rather than leaving it to the order their
+    /// edges were added in.
+    pub fn with_tie_breaker<F>(&mut self, cmp: F) -> &mut Self
+        where F: Fn(&K, &K) -> Ordering + Send + Sync + 'static
+    {
+        self.tie_breaker = Some(TieBreaker(Arc::new(cmp)));
+        self
+    }
+
+    /// Give up with `DepError::ResolutionLimitExceeded` if a resolution
+    /// has to expand more than `n` dependencies, as a guard against
+    /// pathological declarations.  Unlimited by default.
+    pub fn with_max_iterations(&mut self, n: usize) -> &mut Self {
+        self.max_iterations = Some(n);
+        self
+    }
+
+    /// Declare `dependency`.  Returns `false`, and declares nothing, if its
+    /// name is already an alias provided by some other dependency.
+    pub fn add_dependency<T: Dependency<K>>(&mut self, dependency: &T) -> bool {
+        // Gather everything from `dependency` before touching any of our
+        // own state, so a panic in its methods can't leave it half-added.
+        // Anything listed twice is only kept the first time.  A dependency
+        // always owns its own name, so providing it as well means nothing,
+        // and it's dropped from `provides`.
         let name = dependency.name().clone();
+        let provides: Vec<K> = unique(dependency.provides())
+            .into_iter()
+            .filter(|alias| alias != &name)
+            .collect();
+        let sd = InternalDependency {
+            requirements: unique(dependency.requirements()),
+            suggestions: unique(dependency.suggestions()),
+            priorities: provides.iter()
+                .map(|alias| (alias.clone(), dependency.provides_priority(alias)))
+                .collect(),
+            provides,
+            name,
+        };
+        let name = sd.name.clone();
+
+        // Taking our name back from the node that provides it would change
+        // what everything requiring that name gets, and a node nothing can
+        // refer to would be no use either.
+        if matches!(self.provides_map.get(&name), Some(owner) if owner != &name) {
+            return false;
+        }
         let new_node = self.graph.add_node(name.clone());
-        self.node_bucket.insert(name.clone(), new_node.clone());
 
-        let sd = InternalDependency {
-            name: dependency.name().clone(),
-            requirements: dependency.requirements().clone(),
-            suggestions: dependency.suggestions().clone(),
-            provides: dependency.provides().clone(),
+        // Claim our own name, then any aliases.  An alias that some other
+        // node has already claimed keeps pointing at that node, unless we
+        // provide it with a higher priority.
+        self.claim_name(&name, &name, new_node, None);
+        for alias in &sd.provides {
+            self.claim_name(alias, &name, new_node, Some(sd.provides_priority(alias)));
+        }
+
+        self.suggestions.insert(name.clone(), sd.suggestions.clone());
+        self.requirements.insert(name.clone(), sd.requirements.clone());
+        self.dep_map.insert(name, sd);
+        true
+    }
+
+    /// Register a group: a name that requires each of `members`, but that
+    /// doesn't run anything itself.  Resolving it pulls in all of its
+    /// members, but the group is left out of the resulting order, and
+    /// anything requiring it waits on the members instead.  Like
+    /// `add_dependency`, returns `false` if `name` is another dependency's
+    /// alias.
+    pub fn add_group(&mut self, name: K, members: Vec<K>) -> bool {
+        let group = InternalDependency {
+            name: name.clone(),
+            requirements: members,
+            suggestions: vec![],
+            provides: vec![],
+            priorities: HashMap::new(),
         };
-        self.dep_map.insert(name.clone(), sd);
+        if !self.add_dependency(&group) {
+            return false;
+        }
+        self.groups.insert(name);
+        true
+    }
+
+    /// Add `requirement` to the requirements of the dependency `node` refers
+    /// to.  Edges are only created when resolving, so this just updates its
+    /// declaration.
+    pub fn add_requirement(&mut self, node: &K, requirement: K) -> Result<(), DepError<K>> {
+        let name = self.declared_name(node)?;
+        if self.requirements[&name].contains(&requirement) {
+            return Ok(());
+        }
+        self.requirements.get_mut(&name).unwrap().push(requirement.clone());
+        self.dep_map.get_mut(&name).unwrap().requirements.push(requirement);
+        Ok(())
+    }
 
-        // Also add aliases
-        self.provides_map.insert(name.clone(), name.clone());
-        for alias in dependency.provides() {
-            self.node_bucket.insert(alias.clone(), new_node.clone());
-            self.provides_map.insert(alias.clone(), name.clone());
+    /// Add `suggestion` to the suggestions of the dependency `node` refers
+    /// to, in the same way as `add_requirement`.
+    pub fn add_suggestion(&mut self, node: &K, suggestion: K) -> Result<(), DepError<K>> {
+        let name = self.declared_name(node)?;
+        if self.suggestions[&name].contains(&suggestion) {
+            return Ok(());
         }
+        self.suggestions.get_mut(&name).unwrap().push(suggestion.clone());
+        self.dep_map.get_mut(&name).unwrap().suggestions.push(suggestion);
+        Ok(())
+    }
+
+    /// Make the dependency `node` refers to provide `alias` as well, with
+    /// the default priority.  As with `add_dependency`, an alias that is
+    /// already claimed keeps its current owner, and providing a dependency's
+    /// own name does nothing.
+    pub fn add_provides(&mut self, node: &K, alias: K) -> Result<(), DepError<K>> {
+        let name = self.declared_name(node)?;
+        if alias == name {
+            return Ok(());
+        }
+        let index = self.node_bucket[&name];
+        self.claim_name(&alias, &name, index, Some(0));
+        let dep = self.dep_map.get_mut(&name).unwrap();
+        dep.priorities.insert(alias.clone(), 0);
+        dep.provides.push(alias);
+        Ok(())
+    }
+
+    /// The name of the dependency `node` refers to, following aliases.
+    fn declared_name(&self, node: &K) -> Result<K, DepError<K>> {
+        match self.provides_map.get(node) {
+            Some(name) => Ok(name.clone()),
+            None => {
+                Err(DepError::DependencyNotFound(node.clone()))
+            }
+        }
+    }
 
-        self.suggestions.insert(name.clone(), dependency.suggestions().clone());
-        self.requirements.insert(name.clone(), dependency.requirements().clone());
+    /// Attach `tag` to the dependency `name` refers to, for use with
+    /// `resolve_by_tags`.
+    pub fn add_tag(&mut self, name: &K, tag: String) {
+        let name = self.canonical_name(name).clone();
+        self.tags.entry(name).or_default().insert(tag);
+    }
+
+    /// Whether `name` has one of the `include` tags (or `include` is empty)
+    /// and none of the `exclude` tags.
+    fn passes_tags(&self, name: &K, filter: Option<(&[String], &[String])>) -> bool {
+        let (include, exclude) = match filter {
+            Some(filter) => filter,
+            None => return true,
+        };
+        let tags = match self.tags.get(name) {
+            Some(tags) => tags,
+            None => return include.is_empty(),
+        };
+        (include.is_empty() || include.iter().any(|tag| tags.contains(tag))) &&
+        !exclude.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// Point `alias` at `owner`.  If a different node already owns it, it
+    /// only changes hands when both claims are aliases and the new
+    /// `priority` is higher.  A node claims its own name with no priority.
+    fn claim_name(&mut self, alias: &K, owner: &K, node: NodeIndex, priority: Option<i32>) {
+        if let Some(existing) = self.provides_map.get(alias) {
+            if existing != owner {
+                match (priority, self.provider_priority.get(alias)) {
+                    (Some(new), Some(&current)) if new > current => (),
+                    _ => return,
+                }
+            }
+        }
+        self.provides_map.insert(alias.clone(), owner.clone());
+        self.node_bucket.insert(alias.clone(), node);
+        match priority {
+            Some(priority) => self.provider_priority.insert(alias.clone(), priority),
+            None => self.provider_priority.remove(alias),
+        };
     }
 
     pub fn resolve_named_dependencies(&mut self,
                                       dependencies: &Vec<K>)
                                       -> Result<Vec<K>, DepError<K>> {
+        self.resolve_with(dependencies, ResolveOptions::default(), &mut |_| ())
+    }
+
+    /// Resolve `roots` into `out`, replacing whatever it held, so that one
+    /// buffer can be reused across many resolutions.  `out` is left empty
+    /// if resolution fails.
+    pub fn resolve_into(&mut self, roots: &Vec<K>, out: &mut Vec<K>) -> Result<(), DepError<K>> {
+        self.resolve_with_into(roots, ResolveOptions::default(), &mut |_| (), out)?;
+        Ok(())
+    }
+
+    /// Resolve as much of `roots` as possible.  Missing roots, requirements
+    /// and suggestions are left out, as are edges that would form a cycle,
+    /// and each is reported alongside the order instead of ending the
+    /// resolution.  Anything else that goes wrong is reported in the same
+    /// way, with an empty order.
+    pub fn resolve_best_effort(&mut self, roots: &Vec<K>) -> (Vec<K>, Vec<DepError<K>>) {
+        let options = ResolveOptions { best_effort: true, ..ResolveOptions::default() };
+        let mut order = vec![];
+        match self.resolve_with_into(roots, options, &mut |_| (), &mut order) {
+            Ok(problems) => (order, problems),
+            Err(e) => (order, vec![e]),
+        }
+    }
+
+    /// Resolve `roots`, then reorder the result so that dependencies in the
+    /// same group (as given by `group_of`) run back to back wherever the
+    /// edges between them allow.  Otherwise the usual order is kept.
+    pub fn resolve_grouped<F, G>(&mut self,
+                                 roots: &Vec<K>,
+                                 group_of: F)
+                                 -> Result<Vec<K>, DepError<K>>
+        where F: Fn(&K) -> G,
+              G: PartialEq
+    {
+        let order = self.resolve_named_dependencies(roots)?;
+        let mut waiting = vec![0; order.len()];
+        let mut dependents = vec![vec![]; order.len()];
+        for (i, name) in order.iter().enumerate() {
+            let parents: HashSet<usize> = self.resolved_parents(name)
+                .iter()
+                .map(|(parent, _)| self.ranks[parent])
+                .collect();
+            waiting[i] = parents.len();
+            for parent in parents {
+                dependents[parent].push(i);
+            }
+        }
+
+        // Repeatedly take whatever is ready, preferring the current group
+        // and then whatever came first in the usual order.
+        let mut ready: Vec<usize> = (0..order.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut grouped = Vec::with_capacity(order.len());
+        let mut current = None;
+        while !ready.is_empty() {
+            let pick = ready.iter()
+                .cloned()
+                .filter(|&i| current.as_ref() == Some(&group_of(&order[i])))
+                .min()
+                .unwrap_or_else(|| *ready.iter().min().unwrap());
+            ready.retain(|&i| i != pick);
+            for &dependent in &dependents[pick] {
+                waiting[dependent] -= 1;
+                if waiting[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+            current = Some(group_of(&order[pick]));
+            grouped.push(order[pick].clone());
+        }
+
+        self.ranks = grouped.iter().cloned().enumerate().map(|(i, name)| (name, i)).collect();
+        self.resolved = grouped.clone();
+        Ok(grouped)
+    }
+
+    /// Resolve `roots`, leaving out anything in `completed` along with
+    /// anything that was only needed by completed work.  This is meant for
+    /// resuming a run from an external record of what already succeeded.
+    /// The result becomes the order used by `next_runnable`.
+    pub fn resolve_remaining(&mut self,
+                             roots: &Vec<K>,
+                             completed: &HashSet<K>)
+                             -> Result<Vec<K>, DepError<K>> {
+        let order = self.resolve_named_dependencies(roots)?;
+        let completed: HashSet<&K> = completed.iter()
+            .map(|name| self.canonical_name(name))
+            .collect();
+
+        let mut needed = HashSet::new();
+        let mut to_visit: Vec<K> = roots.iter()
+            .map(|root| self.canonical_name(root).clone())
+            .collect();
+        while let Some(name) = to_visit.pop() {
+            if completed.contains(&name) || !needed.insert(name.clone()) {
+                continue;
+            }
+            for (parent, edge) in self.resolved_parents(&name) {
+                if edge != DepEdge::Follows {
+                    to_visit.push(parent);
+                }
+            }
+        }
+
+        let remaining: Vec<K> = order.into_iter().filter(|name| needed.contains(name)).collect();
+        self.ranks = remaining.iter().cloned().enumerate().map(|(i, name)| (name, i)).collect();
+        self.resolved = remaining.clone();
+        Ok(remaining)
+    }
+
+    /// Resolve `roots`, leaving out every dependency that doesn't have one
+    /// of the `include` tags (unless `include` is empty) or that has one of
+    /// the `exclude` tags.  Roots that are left out are simply dropped, and
+    /// so are suggestions, but a left-out requirement is an error.
+    pub fn resolve_by_tags(&mut self,
+                           roots: &Vec<K>,
+                           include: &[String],
+                           exclude: &[String])
+                           -> Result<Vec<K>, DepError<K>> {
+        let options = ResolveOptions {
+            tag_filter: Some((include, exclude)),
+            ..ResolveOptions::default()
+        };
+        self.resolve_with(roots, options, &mut |_| ())
+    }
+
+    /// Resolve `dependencies` for a strict run, where everything suggested
+    /// must succeed too.  A suggestion that can't be found is already an
+    /// error; after this, a failed suggestion also keeps its dependents from
+    /// running, exactly as a failed requirement would.
+    pub fn resolve_promoting_suggestions(&mut self,
+                                         dependencies: &Vec<K>)
+                                         -> Result<Vec<K>, DepError<K>> {
+        let options = ResolveOptions { promote_suggestions: true, ..ResolveOptions::default() };
+        self.resolve_with(dependencies, options, &mut |_| ())
+    }
+
+    /// Resolve `dependencies`, pairing each dependency in the resulting
+    /// order with the dependencies it directly `Requires`.
+    pub fn resolve_with_parents(&mut self,
+                                dependencies: &Vec<K>)
+                                -> Result<Vec<(K, Vec<K>)>, DepError<K>> {
+        let order = self.resolve_named_dependencies(dependencies)?;
+        Ok(order.into_iter()
+            .map(|name| {
+                let parents = self.required_parents_of_named(&name)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                (name, parents)
+            })
+            .collect())
+    }
+
+    /// Resolve `roots`, pairing each dependency in the resulting order with
+    /// the aliases it provides, i.e. what becomes available once it has run.
+    pub fn resolve_annotated(&mut self, roots: &Vec<K>) -> Result<Vec<(K, Vec<K>)>, DepError<K>> {
+        let order = self.resolve_named_dependencies(roots)?;
+        Ok(order.into_iter()
+            .map(|name| {
+                let provides = self.dep_map[&name].provides.clone();
+                (name, provides)
+            })
+            .collect())
+    }
+
+    /// Resolve `dependencies`, writing a JSON object to `sink` for each
+    /// notable step along the way, one per line.
+    #[cfg(feature = "serde")]
+    pub fn resolve_with_events<W: Write>(&mut self,
+                                         dependencies: &Vec<K>,
+                                         sink: &mut W)
+                                         -> Result<Vec<K>, DepError<K>>
+        where K: serde::Serialize
+    {
+        let mut write_error = None;
+        let result = self.resolve_with(dependencies, ResolveOptions::default(), &mut |event| {
+            if write_error.is_some() {
+                return;
+            }
+            let json = match event {
+                ResolveEvent::Visited(name) => serde_json::json!({
+                    "event": "visited",
+                    "name": name,
+                }),
+                ResolveEvent::EdgeAdded(from, to, edge) => serde_json::json!({
+                    "event": "edge_added",
+                    "from": from,
+                    "to": to,
+                    "edge": edge.to_string(),
+                }),
+                ResolveEvent::EdgeExists(from, to, edge) => serde_json::json!({
+                    "event": "edge_exists",
+                    "from": from,
+                    "to": to,
+                    "edge": edge.to_string(),
+                }),
+                ResolveEvent::CycleDropped(from, to) => serde_json::json!({
+                    "event": "cycle_dropped",
+                    "from": from,
+                    "to": to,
+                    "edge": DepEdge::Follows.to_string(),
+                }),
+                ResolveEvent::SuggestionSkipped(name, suggestion) => serde_json::json!({
+                    "event": "suggestion_skipped",
+                    "name": name,
+                    "suggestion": suggestion,
+                }),
+            };
+            let written = serde_json::to_writer(&mut *sink, &json)
+                .map_err(io::Error::from)
+                .and_then(|_| sink.write_all(b"\n"));
+            if let Err(e) = written {
+                write_error = Some(e);
+            }
+        });
+        match write_error {
+            Some(e) => Err(DepError::EventWriteFailed(e)),
+            None => result,
+        }
+    }
+
+    /// Every name in `roots` that isn't registered, either as a dependency
+    /// or as an alias, in the order they were given.
+    pub fn missing_roots(&self, roots: &Vec<K>) -> Vec<K> {
+        roots.iter()
+            .filter(|root| !self.provides_map.contains_key(root))
+            .cloned()
+            .collect()
+    }
+
+    /// Every requirement or suggestion target, across all declared
+    /// dependencies, that is neither a dependency nor an alias.  Each one is
+    /// listed once, in no particular order.
+    pub fn unsatisfied_capabilities(&self) -> Vec<K> {
+        let mut missing = vec![];
+        let mut seen = HashSet::new();
+        for dep in self.dep_map.values() {
+            for target in dep.requirements.iter().chain(dep.suggestions.iter()) {
+                if !self.provides_map.contains_key(target) && seen.insert(target) {
+                    missing.push(target.clone());
+                }
+            }
+        }
+        missing
+    }
+
+    /// Every declared dependency that nothing else requires or suggests,
+    /// in the order they were added.  Resolving these pulls in everything
+    /// the graph knows about.
+    pub fn minimal_roots(&self) -> Vec<K> {
+        let mut needed = HashSet::new();
+        for dep in self.dep_map.values() {
+            for target in dep.requirements.iter().chain(dep.suggestions.iter()) {
+                if let Some(owner) = self.provides_map.get(target) {
+                    if owner != &dep.name {
+                        needed.insert(owner);
+                    }
+                }
+            }
+        }
+
+        self.graph
+            .graph()
+            .node_indices()
+            .filter(|node| self.node_bucket.get(&self.graph[*node]) == Some(node))
+            .map(|node| &self.graph[node])
+            .filter(|name| !needed.contains(name))
+            .cloned()
+            .collect()
+    }
+
+    fn resolve_with(&mut self,
+                    dependencies: &Vec<K>,
+                    options: ResolveOptions,
+                    on_event: &mut dyn FnMut(ResolveEvent<K>))
+                    -> Result<Vec<K>, DepError<K>> {
+        let mut dep_order = vec![];
+        self.resolve_with_into(dependencies, options, on_event, &mut dep_order)?;
+        Ok(dep_order)
+    }
+
+    /// Resolve `dependencies`, leaving the order in `dep_order`.  Whatever
+    /// `dep_order` held before is discarded, and it is left empty if
+    /// resolution fails.  In a best-effort resolution, the problems that
+    /// were skipped over are returned.
+    fn resolve_with_into(&mut self,
+                         dependencies: &Vec<K>,
+                         options: ResolveOptions,
+                         on_event: &mut dyn FnMut(ResolveEvent<K>),
+                         dep_order: &mut Vec<K>)
+                         -> Result<Vec<DepError<K>>, DepError<K>> {
+        dep_order.clear();
+
+        if self.require_roots && dependencies.is_empty() {
+            return Err(DepError::NoRootsProvided);
+        }
+
+        // Leave out any roots that don't exist, if we're allowed to, and
+        // any that have been filtered out.
+        let mut problems = vec![];
+        let known_roots: Vec<K>;
+        let dependencies = if options.best_effort || options.tag_filter.is_some() {
+            known_roots = dependencies.iter()
+                .filter(|root| {
+                    let name = match self.provides_map.get(root) {
+                        Some(name) => name,
+                        None if options.best_effort => {
+                            problems.push(DepError::DependencyNotFound((*root).clone()));
+                            return false;
+                        }
+                        None => return true,
+                    };
+                    self.passes_tags(name, options.tag_filter)
+                })
+                .cloned()
+                .collect();
+            &known_roots
+        } else {
+            dependencies
+        };
 
         let mut to_resolve = dependencies.clone();
+        let mut expanded = HashSet::new();
+        self.duplicates.clear();
 
+        let mut iterations = 0;
         loop {
             if to_resolve.is_empty() {
                 break;
             }
+            iterations += 1;
+            if let Some(limit) = self.max_iterations {
+                if iterations > limit {
+                    return Err(DepError::ResolutionLimitExceeded(limit));
+                }
+            }
 
             // If this dep_name has been resolved, skip it.
-            let dep_name = to_resolve.remove(0);
-            let dep_name = match self.provides_map.get(&dep_name) {
+            let requested = to_resolve.remove(0);
+            let dep_name = match self.provides_map.get(&requested) {
                 Some(s) => s.clone(),
-                None => return Err(DepError::DependencyNotFound(dep_name.clone())),
+                None => return Err(DepError::DependencyNotFound(requested)),
             };
 
+            // Once a cycle has been skipped over, expanding the same
+            // dependency again would go around it forever.
+            if options.best_effort && !expanded.insert(dep_name.clone()) {
+                continue;
+            }
+
+            // Nodes this dependency has already declared an edge from, so
+            // that declaring the same one again can be reported.
+            let mut declared = HashSet::new();
+
             // Resolve all requirements.
             match self.requirements.get(&dep_name) {
-                None => return Err(DepError::RequirementsNotFound(dep_name.clone())),
+                None => {
+                    return Err(DepError::RequirementsNotFound {
+                        requested,
+                        resolved: dep_name,
+                    })
+                }
                 Some(ref reqs) => {
                     for req in *reqs {
-                        to_resolve.push(req.clone());
-                        let target = match self.node_bucket.get(req) {
+                        // Look targets up through `provides_map`, just like roots.
+                        let target_name = match self.provides_map.get(req) {
                             None => {
-                                return Err(DepError::RequirementNotFound(dep_name, req.clone()))
+                                let err = DepError::RequirementNotFound {
+                                    requested: requested.clone(),
+                                    resolved: dep_name.clone(),
+                                    requirement: req.clone(),
+                                };
+                                if !options.best_effort {
+                                    return Err(err);
+                                }
+                                problems.push(err);
+                                continue;
                             }
-                            Some(e) => e,
+                            Some(owner) => owner,
                         };
+                        if !self.passes_tags(target_name, options.tag_filter) {
+                            let err = DepError::RequirementFiltered {
+                                requested: requested.clone(),
+                                resolved: dep_name.clone(),
+                                requirement: req.clone(),
+                            };
+                            if !options.best_effort {
+                                return Err(err);
+                            }
+                            problems.push(err);
+                            continue;
+                        }
+                        to_resolve.push(req.clone());
+                        let target = &self.node_bucket[target_name];
+                        let duplicate = !declared.insert(*target);
 
                         // Don't add extra edges.
                         if self.graph.find_edge(*target, self.node_bucket[&dep_name]).is_some() {
+                            let edge = (target_name.clone(), dep_name.clone(), DepEdge::Requires);
+                            if duplicate && !self.duplicates.contains(&edge) {
+                                self.duplicates.push(edge);
+                            }
+                            on_event(ResolveEvent::EdgeExists(target_name,
+                                                              &dep_name,
+                                                              DepEdge::Requires));
                             continue;
                         }
 
-                        if let Err(_) = self.graph
+                        // `WouldCycle` is the only way `add_edge` can fail.
+                        if let Err(WouldCycle(_)) = self.graph
                             .add_edge(*target, self.node_bucket[&dep_name], DepEdge::Requires) {
-                            return Err(DepError::CircularDependency(dep_name.clone(), req.clone()));
+                            let err = DepError::CircularDependency {
+                                requested: requested.clone(),
+                                resolved: dep_name.clone(),
+                                target: req.clone(),
+                            };
+                            if !options.best_effort {
+                                return Err(err);
+                            }
+                            problems.push(err);
+                            continue;
                         }
+                        on_event(ResolveEvent::EdgeAdded(target_name,
+                                                         &dep_name,
+                                                         DepEdge::Requires));
                     }
                 }
             }
 
             // Also resolve all suggestions.
             match self.suggestions.get(&dep_name) {
-                None => return Err(DepError::SuggestionsNotFound(dep_name.clone())),
+                None => {
+                    return Err(DepError::SuggestionsNotFound {
+                        requested,
+                        resolved: dep_name,
+                    })
+                }
                 Some(ref reqs) => {
                     for req in *reqs {
-                        to_resolve.push(req.clone());
-                        let target = match self.node_bucket.get(req) {
-                            None => return Err(DepError::SuggestionNotFound(dep_name, req.clone())),
-                            Some(e) => e,
+                        // Look targets up through `provides_map`, just like roots.
+                        let target_name = match self.provides_map.get(req) {
+                            None => {
+                                let err = DepError::SuggestionNotFound {
+                                    requested: requested.clone(),
+                                    resolved: dep_name.clone(),
+                                    suggestion: req.clone(),
+                                };
+                                if !options.best_effort {
+                                    return Err(err);
+                                }
+                                problems.push(err);
+                                continue;
+                            }
+                            Some(owner) => owner,
                         };
+                        if !self.passes_tags(target_name, options.tag_filter) {
+                            on_event(ResolveEvent::SuggestionSkipped(&dep_name, target_name));
+                            continue;
+                        }
+                        to_resolve.push(req.clone());
+                        let target = &self.node_bucket[target_name];
+                        let duplicate = !declared.insert(*target);
 
                         // Don't add extra edges.
                         if self.graph.find_edge(*target, self.node_bucket[&dep_name]).is_some() {
+                            let edge = (target_name.clone(), dep_name.clone(), DepEdge::Suggests);
+                            if duplicate && !self.duplicates.contains(&edge) {
+                                self.duplicates.push(edge);
+                            }
+                            on_event(ResolveEvent::EdgeExists(target_name,
+                                                              &dep_name,
+                                                              DepEdge::Suggests));
                             continue;
                         }
 
-                        if let Err(_) = self.graph
+                        // `WouldCycle` is the only way `add_edge` can fail.
+                        if let Err(WouldCycle(_)) = self.graph
                             .add_edge(*target, self.node_bucket[&dep_name], DepEdge::Suggests) {
-                            return Err(DepError::CircularDependency(dep_name.clone(), req.clone()));
+                            let err = DepError::CircularDependency {
+                                requested: requested.clone(),
+                                resolved: dep_name.clone(),
+                                target: req.clone(),
+                            };
+                            if !options.best_effort {
+                                return Err(err);
+                            }
+                            problems.push(err);
+                            continue;
                         }
+                        on_event(ResolveEvent::EdgeAdded(target_name,
+                                                         &dep_name,
+                                                         DepEdge::Suggests));
                     }
                 }
             }
         }
 
         // Add "Follows" dependencies, if no other dependency exists.
-        let num_deps = dependencies.len();
-        for i in 1..num_deps {
-            let previous_dep = dependencies[i - 1].clone();
-            let this_dep = dependencies[i].clone();
-
-            let previous_edge = match self.node_bucket.get(&previous_dep) {
-                Some(s) => s,
-                None => return Err(DepError::DependencyNotFound(previous_dep)),
-            };
-            let this_edge = match self.node_bucket.get(&this_dep) {
-                Some(s) => s,
-                None => return Err(DepError::DependencyNotFound(this_dep)),
-            };
+        if self.follows {
+            let num_deps = dependencies.len();
+            for i in 1..num_deps {
+                let previous_dep = dependencies[i - 1].clone();
+                let this_dep = dependencies[i].clone();
+
+                let previous_edge = match self.node_bucket.get(&previous_dep) {
+                    Some(s) => s,
+                    None => return Err(DepError::DependencyNotFound(previous_dep)),
+                };
+                let this_edge = match self.node_bucket.get(&this_dep) {
+                    Some(s) => s,
+                    None => return Err(DepError::DependencyNotFound(this_dep)),
+                };
+                let previous_name = self.provides_map.get(&previous_dep).unwrap_or(&previous_dep);
+                let this_name = self.provides_map.get(&this_dep).unwrap_or(&this_dep);
+
+                // A root listed twice in a row, possibly under an alias,
+                // can't follow itself.
+                if previous_edge == this_edge {
+                    continue;
+                }
 
-            // Don't add a "Follows" dependency if one already exists.
-            if self.graph.find_edge(*previous_edge, *this_edge).is_some() {
-                continue;
+                // Don't add a "Follows" dependency if one already exists.
+                if self.graph.find_edge(*previous_edge, *this_edge).is_some() {
+                    continue;
+                }
+
+                // If we get a "CircularDependency", that's fine, we just won't add this edge.
+                let event = match self.graph
+                    .add_edge(*previous_edge, *this_edge, DepEdge::Follows) {
+                    Ok(_) => ResolveEvent::EdgeAdded(previous_name, this_name, DepEdge::Follows),
+                    Err(WouldCycle(_)) => ResolveEvent::CycleDropped(previous_name, this_name),
+                };
+                on_event(event);
             }
+        }
 
-            // If we get a "CircularDependency", that's fine, we just won't add this edge.
-            self.graph.add_edge(*previous_edge, *this_edge, DepEdge::Follows).ok();
+        // `Dag::add_edge` refuses any edge that would close a cycle, so this
+        // is only worth checking in debug builds.
+        if cfg!(debug_assertions) {
+            assert!(self.check_acyclic().is_ok(), "resolution left a cycle in the graph");
         }
 
         // Sort everything into a "dependency order"
-        let mut dep_order = vec![];
         let mut seen_nodes = HashMap::new();
         for dep_name in dependencies {
 
             // Pick a node from the bucket and visit it.  This will cause
             // all nodes in the graph to be visited, in order.
             let some_node = self.node_bucket.get(dep_name).unwrap().clone();
-            self.visit_node(&mut seen_nodes, &some_node, &mut dep_order);
+            self.visit_node(&mut seen_nodes, &some_node, options.tag_filter, dep_order);
         }
-        Ok(dep_order)
+        if let Some(TieBreaker(cmp)) = self.tie_breaker.clone() {
+            self.break_ties(&*cmp, dep_order);
+        }
+        dep_order.retain(|name| !self.groups.contains(name));
+        for name in dep_order.iter() {
+            on_event(ResolveEvent::Visited(name));
+        }
+        self.ranks = dep_order.iter().cloned().enumerate().map(|(i, name)| (name, i)).collect();
+        self.resolved.clone_from(dep_order);
+        self.promote_suggestions = options.promote_suggestions;
+        Ok(problems)
     }
 
     pub fn resolve_dependencies<T: Dependency<K>>(&mut self,
@@ -245,205 +1098,2375 @@ impl<K> Dependy<K> where K: Clone + Eq + Hash + fmt::Display {
         self.resolve_named_dependencies(&to_resolve)
     }
 
-    pub fn save_dot(&self, output: &mut File) -> io::Result<()> {
-        write!(output, "{}", Dot::new(self.graph.graph()))
+    /// Add each of `deps` to a fresh `Dependy`, resolve `roots` against it,
+    /// and return the order.  Handy when the graph isn't needed afterwards.
+    pub fn resolve_from<T: Dependency<K>>(deps: Vec<T>,
+                                          roots: &Vec<K>)
+                                          -> Result<Vec<K>, DepError<K>> {
+        let mut depgraph = Dependy::new();
+        for dep in &deps {
+            depgraph.add_dependency(dep);
+        }
+        depgraph.resolve_named_dependencies(roots)
     }
 
-    fn visit_node(&mut self,
-                  seen_nodes: &mut HashMap<NodeIndex, ()>,
-                  node: &NodeIndex,
-                  dep_order: &mut Vec<K>) {
+    /// The underlying graph, for running other `daggy`/`petgraph`
+    /// algorithms over it.  Edges point from a dependency to its dependent.
+    pub fn graph(&self) -> &Dag<K, DepEdge> {
+        &self.graph
+    }
 
-        // If this node has been seen already, don't re-visit it.
-        if seen_nodes.insert(node.clone(), ()).is_some() {
-            return;
+    /// Confirm that the graph has no cycles, returning the dependencies
+    /// making up one if it does.  Resolution checks this in debug builds.
+    pub fn check_acyclic(&self) -> Result<(), DepError<K>> {
+        for scc in kosaraju_scc(self.graph.graph()) {
+            if scc.len() > 1 || self.graph.find_edge(scc[0], scc[0]).is_some() {
+                return Err(DepError::CycleDetected(scc.iter()
+                    .map(|&node| self.graph[node].clone())
+                    .collect()));
+            }
         }
+        Ok(())
+    }
 
-        // 1. Visit all parents
-        // 2. Visit ourselves
-        // 3. Visit all children
+    /// The index of the node `name` refers to, following aliases.
+    pub fn node_index(&self, name: &K) -> Option<NodeIndex> {
+        self.node_bucket.get(name).cloned()
+    }
 
-        let parents = self.graph.parents(*node);
-        let mut to_visit = vec![];
-        for (_, parent_index) in parents.iter(&self.graph) {
-            to_visit.push(parent_index);
-        }
-        for parent_index in to_visit {
-            self.visit_node(seen_nodes, &parent_index, dep_order);
-        }
+    /// The kind of edge leading from `from` to `to`, if there is one.  As
+    /// in `graph`, edges point from a dependency to its dependent, so this
+    /// is `Some(DepEdge::Requires)` when `to` requires `from`.  Aliases are
+    /// followed, and edges only exist once they've been resolved.
+    pub fn relationship(&self, from: &K, to: &K) -> Option<DepEdge> {
+        let from = self.node_bucket.get(from)?;
+        let to = self.node_bucket.get(to)?;
+        self.graph.find_edge(*from, *to).map(|edge| self.graph[edge])
+    }
 
-        dep_order.push(self.graph[*node].clone());
-        // let children = self.graph.children(*node);
-        // let mut to_visit = vec![];
-        // for (_, child_index) in children.iter(&self.graph) {
-        // to_visit.push(child_index);
-        // }
-        // for child_index in to_visit {
-        // self.visit_node(seen_nodes, &child_index, dep_order);
-        // }
-        //
+    /// Drop every edge added during resolution, returning the graph to the
+    /// state it was in when the dependencies were declared.
+    pub fn reset_graph(&mut self) {
+        self.rebuild_graph(false);
     }
 
-    // pub fn parents_of_named(&mut self, name: &String) -> Vec<String> {
-    // let parents = self.graph.parents(self.node_bucket[name]);
-    // let mut retval = vec![];
-    // for (parent, parent_index) in parents.iter(&self.graph) {
-    // retval.push(parent);
-    // }
-    // retval
-    // }
-    //
-    pub fn required_parents_of_named(&self, name: &K) -> Vec<&K> {
-        let parents = self.graph.parents(self.node_bucket[name]);
-        let mut retval = vec![];
-        for (edge, node) in parents.iter(&self.graph) {
-            if *(self.graph.edge_weight(edge).unwrap()) != DepEdge::Requires {
-                continue;
-            }
-            retval.push(self.graph.node_weight(node).unwrap());
+    /// Remove every edge whose kind fails `keep`, leaving the dependencies
+    /// themselves in place.
+    pub fn retain_edges(&mut self, keep: impl Fn(DepEdge) -> bool) {
+        let mut doomed: Vec<_> = self.graph
+            .raw_edges()
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| !keep(edge.weight))
+            .map(|(index, _)| EdgeIndex::new(index))
+            .collect();
+
+        // Removing an edge moves the last one into its slot, so work
+        // backwards to keep the remaining indices valid.
+        doomed.reverse();
+        for edge in doomed {
+            self.graph.remove_edge(edge);
         }
-        retval
+        self.generation += 1;
     }
 
-    pub fn mark_successful(&mut self, dep: &K) {
-        self.results.insert(dep.clone(), true);
+    /// Record the current edges and resolution state, so that a
+    /// speculative resolution can be undone with `restore`.  Resolving only
+    /// ever adds edges, so this is cheap: it doesn't copy the graph.  To
+    /// roll back anything else, clone the whole `Dependy` instead.
+    pub fn snapshot(&self) -> Snapshot<K> {
+        Snapshot {
+            generation: self.generation,
+            edge_count: self.graph.edge_count(),
+            resolved: self.resolved.clone(),
+            ranks: self.ranks.clone(),
+            promote_suggestions: self.promote_suggestions,
+            duplicates: self.duplicates.clone(),
+        }
     }
 
-    pub fn mark_failure(&mut self, dep: &K) {
-        self.results.insert(dep.clone(), false);
+    /// Go back to the edges and resolution state recorded in `snapshot`.
+    /// Dependencies, tags and results added since are kept.
+    ///
+    /// Panics if edges have been removed since the snapshot was taken,
+    /// whether by `retain_edges`, `reset_graph` or `prune_unreachable`.
+    pub fn restore(&mut self, snapshot: Snapshot<K>) {
+        assert!(snapshot.generation == self.generation,
+                "snapshot is stale: edges were removed after it was taken");
+        while self.graph.edge_count() > snapshot.edge_count {
+            let last = EdgeIndex::new(self.graph.edge_count() - 1);
+            self.graph.remove_edge(last);
+        }
+        self.resolved = snapshot.resolved;
+        self.ranks = snapshot.ranks;
+        self.promote_suggestions = snapshot.promote_suggestions;
+        self.duplicates = snapshot.duplicates;
     }
 
-    pub fn reset_results(&mut self) {
+    /// Remove every dependency that can't be reached from `roots` through
+    /// requirements or suggestions, along with its aliases and results.
+    /// Edges between the remaining dependencies are kept.
+    pub fn prune_unreachable(&mut self, roots: &Vec<K>) {
+        let mut keep = HashSet::new();
+        let mut to_visit: Vec<K> = roots.iter()
+            .filter_map(|root| self.provides_map.get(root))
+            .cloned()
+            .collect();
+        while let Some(name) = to_visit.pop() {
+            if !keep.insert(name.clone()) {
+                continue;
+            }
+            let dep = &self.dep_map[&name];
+            for target in dep.requirements.iter().chain(dep.suggestions.iter()) {
+                if let Some(target) = self.provides_map.get(target) {
+                    to_visit.push(target.clone());
+                }
+            }
+        }
+
+        self.dep_map.retain(|name, _| keep.contains(name));
+        self.requirements.retain(|name, _| keep.contains(name));
+        self.suggestions.retain(|name, _| keep.contains(name));
+        self.results.retain(|name, _| keep.contains(name));
+        self.groups.retain(|name| keep.contains(name));
+        self.tags.retain(|name, _| keep.contains(name));
+        self.provides_map.retain(|_, owner| keep.contains(owner));
+        let provides_map = &self.provides_map;
+        self.provider_priority.retain(|alias, _| provides_map.contains_key(alias));
+        self.rebuild_graph(true);
+    }
+
+    /// Replace the graph with a fresh one holding a node for each declared
+    /// dependency, and point `node_bucket` at the new nodes.  If `keep_edges`
+    /// is set, edges between nodes that survive are carried across.  Nodes
+    /// keep the order they had, so they're still in the order they were added.
+    fn rebuild_graph(&mut self, keep_edges: bool) {
+        let old_graph = mem::replace(&mut self.graph, Dag::new());
+        self.generation += 1;
+        let mut nodes = HashMap::new();
+        for node in old_graph.graph().node_indices() {
+            let name = &old_graph[node];
+            if self.dep_map.contains_key(name) && self.node_bucket.get(name) == Some(&node) {
+                nodes.insert(name.clone(), self.graph.add_node(name.clone()));
+            }
+        }
+
+        if keep_edges {
+            for edge in old_graph.raw_edges() {
+                let source = nodes.get(&old_graph[edge.source()]);
+                let target = nodes.get(&old_graph[edge.target()]);
+                if let (Some(&source), Some(&target)) = (source, target) {
+                    if self.graph.find_edge(source, target).is_none() {
+                        self.graph.add_edge(source, target, edge.weight).ok();
+                    }
+                }
+            }
+        }
+
+        self.node_bucket.clear();
+        for (alias, owner) in &self.provides_map {
+            self.node_bucket.insert(alias.clone(), nodes[owner]);
+        }
+        self.resolved.clear();
+        self.ranks.clear();
+
+        if cfg!(debug_assertions) {
+            self.assert_consistent();
+        }
+    }
+
+    /// Check that the internal maps all agree with one another and with the
+    /// graph, panicking if they don't.  This is run automatically after the
+    /// graph is rebuilt in debug builds.
+    pub fn assert_consistent(&self) {
+        for (name, dep) in &self.dep_map {
+            assert!(&dep.name == name, "dependency {} is stored as {}", dep.name, name);
+            assert!(self.requirements.get(name) == Some(&dep.requirements),
+                    "requirements of {} are out of sync",
+                    name);
+            assert!(self.suggestions.get(name) == Some(&dep.suggestions),
+                    "suggestions of {} are out of sync",
+                    name);
+            assert!(self.provides_map.contains_key(name), "{} doesn't resolve", name);
+        }
+        for name in self.requirements.keys().chain(self.suggestions.keys()) {
+            assert!(self.dep_map.contains_key(name), "{} was never declared", name);
+        }
+
+        for (alias, owner) in &self.provides_map {
+            assert!(self.dep_map.contains_key(owner),
+                    "{} is provided by undeclared {}",
+                    alias,
+                    owner);
+            let node = match self.node_bucket.get(alias) {
+                Some(node) => *node,
+                None => panic!("{} has no node", alias),
+            };
+            assert!(self.graph.node_weight(node) == Some(owner),
+                    "{} points at the wrong node",
+                    alias);
+        }
+        for alias in self.node_bucket.keys().chain(self.provider_priority.keys()) {
+            assert!(self.provides_map.contains_key(alias), "{} has no provider", alias);
+        }
+
+        assert!(self.ranks.len() == self.resolved.len(), "ranks are out of sync");
+        for (rank, name) in self.resolved.iter().enumerate() {
+            assert!(self.ranks.get(name) == Some(&rank), "rank of {} is wrong", name);
+        }
+    }
+
+    /// Write the graph out in Graphviz DOT format.  Each node is labelled
+    /// with its name, followed by the aliases it provides, if any.
+    ///
+    /// This only needs `fmt::Write`, so the output can go into a `String`.
+    /// That doesn't make the crate usable without `std`, though: daggy,
+    /// petgraph and the `HashMap`s used here all require it.
+    pub fn write_dot<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        write!(out, "{}", Dot::new(&self.dot_graph()))
+    }
+
+    /// Save the graph to `output` in the same format as `write_dot`.
+    pub fn save_dot(&self, output: &mut File) -> io::Result<()> {
+        write!(output, "{}", Dot::new(&self.dot_graph()))
+    }
+
+    /// A copy of the graph with each node replaced by its DOT label.
+    fn dot_graph(&self) -> Graph<String, DepEdge> {
+        let label = |node, name: &K| {
+            let provides = match self.dep_map.get(name) {
+                Some(dep) if self.node_bucket.get(name) == Some(&node) => &dep.provides,
+                _ => return name.to_string(),
+            };
+            if provides.is_empty() {
+                return name.to_string();
+            }
+            let provides: Vec<String> = provides.iter().map(|alias| alias.to_string()).collect();
+            format!("{}\nprovides: {}", name, provides.join(", "))
+        };
+        self.graph.graph().map(label, |_, edge| *edge)
+    }
+
+    /// Write `order` out as CSV, with one row per dependency giving its
+    /// position, its name, its direct requirements and what it provides.
+    /// List columns are separated by `;`, and fields are quoted as
+    /// described in RFC 4180.
+    pub fn write_order_csv<W: Write>(&self, order: &[K], out: &mut W) -> io::Result<()> {
+        write!(out, "position,name,direct_requirements,provides\r\n")?;
+        for (position, name) in order.iter().enumerate() {
+            let (requirements, provides) = match self.dep_map.get(self.canonical_name(name)) {
+                Some(dep) => (join_names(&dep.requirements), join_names(&dep.provides)),
+                None => (String::new(), String::new()),
+            };
+            write!(out,
+                   "{},{},{},{}\r\n",
+                   position,
+                   csv_field(&name.to_string()),
+                   csv_field(&requirements),
+                   csv_field(&provides))?;
+        }
+        Ok(())
+    }
+
+    /// Write `order` out as a shell script that runs each dependency in
+    /// turn, stopping at the first failure.  `cmd_for` gives the command
+    /// line for each dependency.
+    pub fn write_script<W, F>(&self, order: &[K], cmd_for: F, out: &mut W) -> io::Result<()>
+        where W: Write,
+              F: Fn(&K) -> String
+    {
+        write!(out, "#!/bin/sh\nset -e\n")?;
+        for name in order {
+            writeln!(out, "{}", cmd_for(name))?;
+        }
+        Ok(())
+    }
+
+    /// Write out everything `root` requires as an indented tree, in the
+    /// style of `cargo tree`.  Aliases are shown as the dependency they
+    /// resolve to, and a dependency that has already been shown is marked
+    /// with `(*)` rather than being expanded again.
+    pub fn write_tree<W: Write>(&self, root: &K, out: &mut W) -> io::Result<()> {
+        let root = self.canonical_name(root);
+        writeln!(out, "{}", root)?;
+        let mut seen = HashSet::new();
+        seen.insert(root);
+        self.write_subtree(root, "", &mut seen, out)
+    }
+
+    fn write_subtree<'a, W: Write>(&'a self,
+                                   name: &'a K,
+                                   prefix: &str,
+                                   seen: &mut HashSet<&'a K>,
+                                   out: &mut W)
+                                   -> io::Result<()> {
+        let reqs = match self.requirements.get(name) {
+            Some(reqs) => reqs,
+            None => return Ok(()),
+        };
+        for (i, req) in reqs.iter().enumerate() {
+            let req = self.canonical_name(req);
+            let (branch, indent) = if i + 1 == reqs.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            if !seen.insert(req) {
+                writeln!(out, "{}{}{} (*)", prefix, branch, req)?;
+                continue;
+            }
+            writeln!(out, "{}{}{}", prefix, branch, req)?;
+            self.write_subtree(req, &format!("{}{}", prefix, indent), seen, out)?;
+        }
+        Ok(())
+    }
+
+    /// Reorder `order` so that whenever more than one dependency could go
+    /// next, the one `cmp` puts first does.  Groups go as soon as they can,
+    /// since they're about to be dropped from the order anyway.
+    fn break_ties(&self, cmp: &TieBreakFn<K>, order: &mut Vec<K>) {
+        let position: HashMap<&K, usize> = order.iter()
+            .enumerate()
+            .map(|(i, name)| (name, i))
+            .collect();
+        let mut waiting = vec![0; order.len()];
+        let mut dependents = vec![vec![]; order.len()];
+        for (i, name) in order.iter().enumerate() {
+            let parents: HashSet<usize> = self.graph
+                .parents(self.node_bucket[name])
+                .iter(&self.graph)
+                .filter_map(|(_, parent)| position.get(&self.graph[parent]).cloned())
+                .collect();
+            waiting[i] = parents.len();
+            for parent in parents {
+                dependents[parent].push(i);
+            }
+        }
+
+        let mut ready: Vec<usize> = (0..order.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut sorted = Vec::with_capacity(order.len());
+        while let Some(pick) = ready.iter().cloned().min_by(|&a, &b| {
+            let is_dep = |i: usize| !self.groups.contains(&order[i]);
+            is_dep(a).cmp(&is_dep(b)).then_with(|| cmp(&order[a], &order[b])).then(a.cmp(&b))
+        }) {
+            ready.retain(|&i| i != pick);
+            for &dependent in &dependents[pick] {
+                waiting[dependent] -= 1;
+                if waiting[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+            sorted.push(order[pick].clone());
+        }
+        *order = sorted;
+    }
+
+    fn visit_node(&mut self,
+                  seen_nodes: &mut HashMap<NodeIndex, ()>,
+                  node: &NodeIndex,
+                  filter: Option<(&[String], &[String])>,
+                  dep_order: &mut Vec<K>) {
+
+        // If this node has been seen already, don't re-visit it.
+        if seen_nodes.insert(node.clone(), ()).is_some() {
+            return;
+        }
+
+        // Edges left over from earlier resolutions may lead to dependencies
+        // that are filtered out this time.  Don't walk through them, or
+        // their requirements would get pulled in too.
+        if !self.passes_tags(&self.graph[*node], filter) {
+            return;
+        }
+
+        // 1. Visit all parents
+        // 2. Visit ourselves
+        // 3. Visit all children
+
+        let parents = self.graph.parents(*node);
+        let mut to_visit = vec![];
+        for (_, parent_index) in parents.iter(&self.graph) {
+            to_visit.push(parent_index);
+        }
+        for parent_index in to_visit {
+            self.visit_node(seen_nodes, &parent_index, filter, dep_order);
+        }
+
+        dep_order.push(self.graph[*node].clone());
+        // let children = self.graph.children(*node);
+        // let mut to_visit = vec![];
+        // for (_, child_index) in children.iter(&self.graph) {
+        // to_visit.push(child_index);
+        // }
+        // for child_index in to_visit {
+        // self.visit_node(seen_nodes, &child_index, dep_order);
+        // }
+        //
+    }
+
+    // pub fn parents_of_named(&mut self, name: &String) -> Vec<String> {
+    // let parents = self.graph.parents(self.node_bucket[name]);
+    // let mut retval = vec![];
+    // for (parent, parent_index) in parents.iter(&self.graph) {
+    // retval.push(parent);
+    // }
+    // retval
+    // }
+    //
+    pub fn required_parents_of_named(&self, name: &K) -> Vec<&K> {
+        let parents_of = |node| {
+            let mut parents = vec![];
+            for (edge, parent) in self.graph.parents(node).iter(&self.graph) {
+                if self.graph[edge] == DepEdge::Requires {
+                    parents.push(parent);
+                }
+            }
+            parents
+        };
+        let mut retval = vec![];
+        self.collect_required_parents(self.node_bucket[name], &parents_of, &mut retval);
+        retval
+    }
+
+    /// Add the names of the `Requires` parents of `node`, as given by
+    /// `parents_of`, to `out`.  Groups never run, so their members are
+    /// added in their place.
+    fn collect_required_parents<'a>(&'a self,
+                                    node: NodeIndex,
+                                    parents_of: &dyn Fn(NodeIndex) -> Vec<NodeIndex>,
+                                    out: &mut Vec<&'a K>) {
+        for parent in parents_of(node) {
+            let parent_name = &self.graph[parent];
+            if self.groups.contains(parent_name) {
+                self.collect_required_parents(parent, parents_of, out);
+            } else if !out.contains(&parent_name) {
+                out.push(parent_name);
+            }
+        }
+    }
+
+    /// Requirements reachable from `name`, paired with how far away they
+    /// are.  Direct requirements are at depth 0, and nothing deeper than
+    /// `max_depth` is returned.  Each requirement appears once, at the
+    /// shallowest depth it was found.
+    pub fn requirements_within(&self, name: &K, max_depth: usize) -> Vec<(K, usize)> {
+        let mut found = vec![];
+        let start = match self.provides_map.get(name) {
+            Some(s) => s.clone(),
+            None => return found,
+        };
+
+        let mut seen = HashSet::new();
+        seen.insert(start.clone());
+        let mut frontier = vec![start];
+        let mut depth = 0;
+        while !frontier.is_empty() && depth <= max_depth {
+            let mut next = vec![];
+            for node in &frontier {
+                for req in self.requirements.get(node).into_iter().flatten() {
+                    let req = self.canonical_name(req);
+                    if seen.insert(req.clone()) {
+                        found.push((req.clone(), depth));
+                        next.push(req.clone());
+                    }
+                }
+            }
+            frontier = next;
+            depth += 1;
+        }
+        found
+    }
+
+    /// Whether `a` requires `b`, either directly or through a chain of
+    /// other requirements.  Unknown names never require anything.
+    pub fn requires_transitively(&self, a: &K, b: &K) -> bool {
+        let (a, b) = match (self.provides_map.get(a), self.provides_map.get(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+
+        let mut seen = HashSet::new();
+        let mut to_visit = vec![a];
+        while let Some(node) = to_visit.pop() {
+            for req in self.requirements.get(node).into_iter().flatten() {
+                let req = self.canonical_name(req);
+                if req == b {
+                    return true;
+                }
+                if seen.insert(req) {
+                    to_visit.push(req);
+                }
+            }
+        }
+        false
+    }
+
+    /// Everything `root` requires through more than one distinct chain of
+    /// requirements, i.e. where two paths from `root` meet again.  Like
+    /// `requirements_within`, this follows the declarations rather than the
+    /// graph, so no resolution is needed.  The nearest are listed first.
+    pub fn diamonds(&self, root: &K) -> Vec<K> {
+        let root = match self.provides_map.get(root) {
+            Some(r) => r,
+            None => return vec![],
+        };
+        let mut order = vec![];
+        self.requirements_postorder(root, &mut HashSet::new(), &mut order);
+        order.reverse();
+
+        // Count the paths reaching each requirement, stopping at two.
+        let mut paths: HashMap<&K, u8> = HashMap::new();
+        paths.insert(root, 1);
+        for name in &order {
+            let here = paths[name];
+            let reqs: HashSet<&K> = self.requirements[*name]
+                .iter()
+                .filter_map(|req| self.provides_map.get(req))
+                .collect();
+            for req in reqs {
+                let count = paths.entry(req).or_insert(0);
+                *count = (*count + here).min(2);
+            }
+        }
+        order.into_iter().filter(|name| paths[name] > 1).cloned().collect()
+    }
+
+    /// Add `name` and everything it requires to `order`, each one after
+    /// all of its own requirements.
+    fn requirements_postorder<'a>(&'a self,
+                                  name: &'a K,
+                                  seen: &mut HashSet<&'a K>,
+                                  order: &mut Vec<&'a K>) {
+        if !seen.insert(name) {
+            return;
+        }
+        for req in self.requirements.get(name).into_iter().flatten() {
+            if let Some(req) = self.provides_map.get(req) {
+                self.requirements_postorder(req, seen, order);
+            }
+        }
+        order.push(name);
+    }
+
+    /// Everything that both `a` and `b` require, directly or indirectly, in
+    /// the order they're reached from `a`.
+    pub fn common_requirements(&self, a: &K, b: &K) -> Vec<K> {
+        let b_requirements: HashSet<K> = self.requirements_within(b, usize::MAX)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        self.requirements_within(a, usize::MAX)
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| b_requirements.contains(name))
+            .collect()
+    }
+
+    /// The common requirements of `a` and `b` that no other common
+    /// requirement depends on.  These are the nearest work the two share.
+    pub fn closest_common_requirements(&self, a: &K, b: &K) -> Vec<K> {
+        let common = self.common_requirements(a, b);
+        common.iter()
+            .filter(|&name| {
+                !common.iter().any(|other| other != name && self.requires_transitively(other, name))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Where `name` appeared in the order produced by the most recent
+    /// resolution, if it was part of it.
+    pub fn rank_of(&self, name: &K) -> Option<usize> {
+        self.ranks.get(self.canonical_name(name)).cloned()
+    }
+
+    /// Every dependency that lists `capability` among the things it
+    /// provides, whether or not it is the one `capability` resolves to.
+    /// The order is unspecified.
+    pub fn candidates_for(&self, capability: &K) -> Vec<&K> {
+        self.dep_map
+            .values()
+            .filter(|dep| dep.provides.contains(capability))
+            .map(|dep| &dep.name)
+            .collect()
+    }
+
+    /// Every name and alias, and the dependency it currently resolves to.
+    /// Each dependency's own name maps to itself.
+    pub fn capability_map(&self) -> &HashMap<K, K> {
+        &self.provides_map
+    }
+
+    /// Edges that were declared more than once during the most recent
+    /// resolution, and so were skipped.  Each is given as the requirement,
+    /// the dependent that declared it again, and the kind of the skipped
+    /// declaration, which may differ from that of the edge already present.
+    pub fn duplicate_edges(&self) -> &[(K, K, DepEdge)] {
+        &self.duplicates
+    }
+
+    /// The number of `Requires` edges leading into `name`, i.e. how many
+    /// dependencies it requires.  Only edges added by resolution count.
+    pub fn requirement_count(&self, name: &K) -> usize {
+        match self.node_bucket.get(name) {
+            Some(node) => {
+                self.graph
+                    .parents(*node)
+                    .iter(&self.graph)
+                    .filter(|&(edge, _)| self.graph[edge] == DepEdge::Requires)
+                    .count()
+            }
+            None => 0,
+        }
+    }
+
+    /// The number of `Requires` edges leading out of `name`, i.e. how many
+    /// dependencies require it.  Only edges added by resolution count.
+    pub fn dependent_count(&self, name: &K) -> usize {
+        match self.node_bucket.get(name) {
+            Some(node) => {
+                self.graph
+                    .children(*node)
+                    .iter(&self.graph)
+                    .filter(|&(edge, _)| self.graph[edge] == DepEdge::Requires)
+                    .count()
+            }
+            None => 0,
+        }
+    }
+
+    /// `required_parents_of_named` for each of `names`, computed in a
+    /// single pass over the graph.  Names that aren't registered are left
+    /// out of the result.
+    pub fn required_parents_of_all(&self, names: &[K]) -> HashMap<K, Vec<K>> {
+        // Walk the edges newest-first, to match the order `parents()` uses.
+        let mut parents: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::new();
+        for edge in self.graph.raw_edges().iter().rev() {
+            if edge.weight == DepEdge::Requires {
+                parents.entry(edge.target()).or_insert_with(Vec::new).push(edge.source());
+            }
+        }
+        let parents_of = |node| parents.get(&node).cloned().unwrap_or_default();
+
+        let mut retval = HashMap::new();
+        for name in names {
+            if let Some(&node) = self.node_bucket.get(name) {
+                let mut found = vec![];
+                self.collect_required_parents(node, &parents_of, &mut found);
+                retval.insert(name.clone(), found.into_iter().cloned().collect());
+            }
+        }
+        retval
+    }
+
+    pub fn mark_successful(&mut self, dep: &K) {
+        let name = self.canonical_name(dep).clone();
+        self.results.insert(name, true);
+    }
+
+    pub fn mark_failure(&mut self, dep: &K) {
+        let name = self.canonical_name(dep).clone();
+        self.results.insert(name, false);
+    }
+
+    /// Like `mark_successful`, but fails if `dep` isn't registered rather
+    /// than recording a result nothing will ever look at.
+    pub fn try_mark_successful(&mut self, dep: &K) -> Result<(), DepError<K>> {
+        let name = self.declared_name(dep)?;
+        self.results.insert(name, true);
+        Ok(())
+    }
+
+    /// Like `mark_failure`, but fails if `dep` isn't registered.
+    pub fn try_mark_failure(&mut self, dep: &K) -> Result<(), DepError<K>> {
+        let name = self.declared_name(dep)?;
+        self.results.insert(name, false);
+        Ok(())
+    }
+
+    /// Forget the result recorded for `dep`, so that it becomes runnable
+    /// again.  Returns the result that was removed, if there was one.
+    pub fn clear_result(&mut self, dep: &K) -> Option<bool> {
+        let name = self.canonical_name(dep).clone();
+        self.results.remove(&name)
+    }
+
+    /// Dependencies from the last resolution that have no result yet, and
+    /// whose parents have all finished.  A `Requires` parent must have
+    /// succeeded, while a `Suggests` parent may have failed or been skipped.
+    pub fn next_runnable(&self) -> Vec<K> {
+        let skipped = self.skipped_dependencies();
+        let mut runnable = vec![];
+        'nodes: for name in &self.resolved {
+            if self.results.contains_key(name) || skipped.contains(name) {
+                continue;
+            }
+            for (parent, edge) in self.resolved_parents(name) {
+                match (edge, self.results.get(&parent)) {
+                    (DepEdge::Requires, Some(&true)) => (),
+                    (DepEdge::Suggests, Some(_)) => (),
+                    (DepEdge::Suggests, None) if skipped.contains(&parent) => (),
+                    (DepEdge::Follows, _) => (),
+                    _ => continue 'nodes,
+                }
+            }
+            runnable.push(name.clone());
+        }
+        runnable
+    }
+
+    /// Dependencies from the last resolution that can never run, because
+    /// something they require (directly or indirectly) has failed.
+    pub fn skipped_dependencies(&self) -> Vec<K> {
+        let mut skipped: Vec<K> = vec![];
+        for name in &self.resolved {
+            if self.results.contains_key(name) {
+                continue;
+            }
+            for (parent, edge) in self.resolved_parents(name) {
+                if edge != DepEdge::Requires {
+                    continue;
+                }
+                if self.results.get(&parent) == Some(&false) || skipped.contains(&parent) {
+                    skipped.push(name.clone());
+                    break;
+                }
+            }
+        }
+        skipped
+    }
+
+    /// Explain the current state of the last resolution, in particular why
+    /// `next_runnable` has nothing to offer.
+    pub fn resolution_state(&self) -> ResolutionState<K> {
+        let runnable = self.next_runnable();
+        if !runnable.is_empty() {
+            return ResolutionState::Ready(runnable);
+        }
+
+        let skipped = self.skipped_dependencies();
+        let stuck: Vec<K> = self.resolved
+            .iter()
+            .filter(|name| !self.results.contains_key(name) && !skipped.contains(name))
+            .cloned()
+            .collect();
+        if !stuck.is_empty() {
+            ResolutionState::Deadlocked(stuck)
+        } else if !skipped.is_empty() {
+            ResolutionState::Blocked(skipped)
+        } else {
+            ResolutionState::Complete
+        }
+    }
+
+    /// Each dependency in the order produced by the most recent resolution,
+    /// along with whether it is a root, a leaf, or both.
+    pub fn node_kinds(&self) -> Vec<(K, NodeKind)> {
+        let mut has_dependents = HashSet::new();
+        let mut has_requirements = HashSet::new();
+        let in_order = |name| self.ranks.contains_key(name) || self.groups.contains(name);
+        for edge in self.graph.raw_edges() {
+            if edge.weight == DepEdge::Follows {
+                continue;
+            }
+            let source = &self.graph[edge.source()];
+            let target = &self.graph[edge.target()];
+            if in_order(source) && in_order(target) {
+                has_dependents.insert(source);
+                has_requirements.insert(target);
+            }
+        }
+
+        self.resolved
+            .iter()
+            .map(|name| {
+                let kind = match (has_dependents.contains(name), has_requirements.contains(name)) {
+                    (false, false) => NodeKind::Both,
+                    (false, true) => NodeKind::Root,
+                    (true, false) => NodeKind::Leaf,
+                    (true, true) => NodeKind::Internal,
+                };
+                (name.clone(), kind)
+            })
+            .collect()
+    }
+
+    /// The parents of `name` that were part of the last resolution, along
+    /// with the kind of edge connecting them.  Suggestions are reported as
+    /// requirements if that resolution promoted them.  Groups are looked
+    /// through, so a group's members are reported in its place, connected
+    /// by the weaker of the two edges.
+    fn resolved_parents(&self, name: &K) -> Vec<(K, DepEdge)> {
+        let mut parents = vec![];
+        let node = match self.node_bucket.get(name) {
+            Some(n) => *n,
+            None => return parents,
+        };
+        for (edge, parent) in self.graph.parents(node).iter(&self.graph) {
+            let parent_name = &self.graph[parent];
+            let edge = match self.graph[edge] {
+                DepEdge::Suggests if self.promote_suggestions => DepEdge::Requires,
+                edge => edge,
+            };
+            if self.groups.contains(parent_name) {
+                for (member, member_edge) in self.resolved_parents(parent_name) {
+                    let edge = match (edge, member_edge) {
+                        (DepEdge::Follows, _) | (_, DepEdge::Follows) => DepEdge::Follows,
+                        (DepEdge::Suggests, _) | (_, DepEdge::Suggests) => DepEdge::Suggests,
+                        _ => DepEdge::Requires,
+                    };
+                    parents.push((member, edge));
+                }
+            } else if self.resolved.contains(parent_name) {
+                parents.push((parent_name.clone(), edge));
+            }
+        }
+        parents
+    }
+
+    /// Map an alias onto the name of the dependency providing it.  Unknown
+    /// names are returned as-is.
+    fn canonical_name<'a>(&'a self, name: &'a K) -> &'a K {
+        self.provides_map.get(name).unwrap_or(name)
+    }
+
+    /// Forget every recorded result, returning how many there were.
+    pub fn reset_results(&mut self) -> usize {
+        let cleared = self.results.len();
         self.results.clear();
+        cleared
+    }
+}
+
+/// `list` with any repeated entries removed, keeping the first of each.
+fn unique<K: Clone + Eq + Hash>(list: &[K]) -> Vec<K> {
+    let mut seen = HashSet::new();
+    list.iter().filter(|item| seen.insert(*item)).cloned().collect()
+}
+
+fn join_names<K: fmt::Display>(names: &[K]) -> String {
+    names.iter().map(|name| name.to_string()).collect::<Vec<_>>().join(";")
+}
+
+/// Quote a CSV field if it contains anything that would otherwise be
+/// mistaken for a separator.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Two graphs are equal when they declare the same dependencies and aliases,
+/// and contain the same edges between the same names.  Node indices, and the
+/// results of any runs, are not compared.
+impl<K> PartialEq for Dependy<K> where K: Clone + Eq + Hash {
+    fn eq(&self, other: &Dependy<K>) -> bool {
+        self.dep_map == other.dep_map && self.provides_map == other.provides_map &&
+        self.groups == other.groups && self.tags == other.tags &&
+        node_names(&self.graph) == node_names(&other.graph) &&
+        named_edges(&self.graph) == named_edges(&other.graph)
+    }
+}
+
+fn node_names<K>(graph: &Dag<K, DepEdge>) -> HashSet<&K>
+    where K: Eq + Hash
+{
+    graph.raw_nodes().iter().map(|node| &node.weight).collect()
+}
+
+fn named_edges<K>(graph: &Dag<K, DepEdge>) -> HashSet<(&K, &K, DepEdge)>
+    where K: Eq + Hash
+{
+    graph.raw_edges()
+        .iter()
+        .map(|edge| (&graph[edge.source()], &graph[edge.target()], edge.weight))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    struct SimpleDep {
+        name: String,
+        requirements: Vec<String>,
+        suggestions: Vec<String>,
+        provides: Vec<String>,
+    }
+    impl SimpleDep {
+        pub fn new(name: &str,
+                   requirements: Vec<String>,
+                   suggestions: Vec<String>,
+                   provides: Vec<String>)
+                   -> SimpleDep {
+            SimpleDep {
+                name: name.to_owned(),
+                requirements: requirements,
+                suggestions: suggestions,
+                provides: provides,
+            }
+        }
+    }
+    impl Dependency<String> for SimpleDep {
+        fn name(&self) -> &String {
+            &self.name
+        }
+        fn requirements(&self) -> &Vec<String> {
+            &self.requirements
+        }
+        fn suggestions(&self) -> &Vec<String> {
+            &self.suggestions
+        }
+        fn provides(&self) -> &Vec<String> {
+            &self.provides
+        }
+    }
+    struct StrDep<'a> {
+        name: &'a str,
+        requirements: Vec<&'a str>,
+        none: Vec<&'a str>,
+    }
+    impl<'a> Dependency<&'a str> for StrDep<'a> {
+        fn name(&self) -> &&'a str {
+            &self.name
+        }
+        fn requirements(&self) -> &Vec<&'a str> {
+            &self.requirements
+        }
+        fn suggestions(&self) -> &Vec<&'a str> {
+            &self.none
+        }
+        fn provides(&self) -> &Vec<&'a str> {
+            &self.none
+        }
+    }
+
+    #[test]
+    fn borrowed_names() {
+        let manifest = String::from("first second third");
+        let names: Vec<&str> = manifest.split(' ').collect();
+
+        let mut depgraph = Dependy::new();
+        for pair in names.windows(2) {
+            depgraph.add_dependency(&StrDep {
+                name: pair[0],
+                requirements: vec![pair[1]],
+                none: vec![],
+            });
+        }
+        depgraph.add_dependency(&StrDep {
+            name: names[2],
+            requirements: vec![],
+            none: vec![],
+        });
+
+        let dep_chain = depgraph.resolve_named_dependencies(&vec![names[0]]).unwrap();
+        assert_eq!(dep_chain, vec!["third", "second", "first"]);
+    }
+
+    #[test]
+    fn single_dep() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("single", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain.len(), 1);
+        assert_eq!(dep_chain[0], "single");
+    }
+
+    #[test]
+    fn empty_roots() {
+        let mut depgraph: Dependy<String> = Dependy::new();
+        assert!(depgraph.resolve_named_dependencies(&vec![]).unwrap().is_empty());
+
+        depgraph.with_require_roots(true);
+        match depgraph.resolve_named_dependencies(&vec![]) {
+            Err(DepError::NoRootsProvided) => (),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn missing_alias_reports_request() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec!["premier".to_string()]);
+        depgraph.add_dependency(&d1);
+
+        match depgraph.resolve_named_dependencies(&vec!["erste".to_string()]) {
+            Err(DepError::DependencyNotFound(requested)) => {
+                assert_eq!(requested, "erste")
+            }
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn missing_requirement_reports_alias() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["missing".to_string()],
+                                vec![],
+                                vec!["premier".to_string()]);
+        depgraph.add_dependency(&d1);
+
+        match depgraph.resolve_named_dependencies(&vec!["premier".to_string()]) {
+            Err(DepError::RequirementNotFound { requested, resolved, requirement }) => {
+                assert_eq!(requested, "premier");
+                assert_eq!(resolved, "first");
+                assert_eq!(requirement, "missing");
+            }
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn missing_roots() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec!["premier".to_string()]);
+        depgraph.add_dependency(&d1);
+
+        let roots = vec!["x".to_string(), "premier".to_string(), "z".to_string()];
+        assert_eq!(depgraph.missing_roots(&roots), vec!["x", "z"]);
+    }
+
+    #[test]
+    fn minimal_roots() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec!["fourth".to_string()], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
+        let d5 = SimpleDep::new("fifth", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.add_dependency(&d5);
+        assert_eq!(depgraph.minimal_roots(), vec!["first", "third", "fifth"]);
+
+        depgraph.resolve_named_dependencies(&vec!["first".to_string()]).unwrap();
+        depgraph.reset_graph();
+        assert_eq!(depgraph.minimal_roots(), vec!["first", "third", "fifth"]);
+        depgraph.prune_unreachable(&vec!["fifth".to_string(), "third".to_string()]);
+        assert_eq!(depgraph.minimal_roots(), vec!["third", "fifth"]);
+    }
+
+    #[test]
+    fn unsatisfied_capabilities() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["deux".to_string(), "missing".to_string()],
+                                vec!["absent".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second",
+                                vec!["missing".to_string()],
+                                vec![],
+                                vec!["deux".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let mut missing = depgraph.unsatisfied_capabilities();
+        missing.sort();
+        assert_eq!(missing, vec!["absent", "missing"]);
+    }
+
+    #[test]
+    fn repeated_requirements() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(),
+                                     "third".to_string(),
+                                     "second".to_string()],
+                                vec!["third".to_string(), "third".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_requirement(&"first".to_string(), "third".to_string()).unwrap();
+        assert_eq!(depgraph.requirements[&"first".to_string()], vec!["second", "third"]);
+        assert_eq!(depgraph.suggestions[&"first".to_string()], vec!["third"]);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain.len(), 3);
+        assert_eq!(dep_chain[2], "first");
+        assert_eq!(depgraph.duplicate_edges(),
+                   &[("third".to_string(), "first".to_string(), DepEdge::Suggests)][..]);
+    }
+
+    #[test]
+    fn two_deps() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain.len(), 2);
+        assert_eq!(dep_chain[0], "second");
+        assert_eq!(dep_chain[1], "first");
+    }
+
+    #[test]
+    fn three_deps() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain.len(), 3);
+        assert_eq!(dep_chain[0], "third");
+        assert_eq!(dep_chain[1], "second");
+        assert_eq!(dep_chain[2], "first");
+    }
+
+    #[test]
+    fn check_acyclic() {
+        let mut depgraph = Dependy::new();
+        assert!(depgraph.check_acyclic().is_ok());
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec!["third".to_string()], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        depgraph.resolve_named_dependencies(&vec!["third".to_string(), "first".to_string()])
+            .unwrap();
+        assert!(depgraph.check_acyclic().is_ok());
+    }
+
+    #[test]
+    fn repeated_roots() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec!["premier".to_string()]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let roots = vec!["first".to_string(), "premier".to_string(), "first".to_string()];
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap(), vec!["first"]);
+        assert_eq!(depgraph.graph().edge_count(), 0);
+
+        let roots = vec!["premier".to_string(), "first".to_string(), "second".to_string()];
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap(),
+                   vec!["first", "second"]);
+        let (order, problems) = depgraph.resolve_best_effort(&roots);
+        assert_eq!(order, vec!["first", "second"]);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn resolve_from() {
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let dep_chain = Dependy::resolve_from(vec![d1, d2], &vec!["first".to_string()]).unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+    }
+
+    #[test]
+    fn resolve_into() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let mut order = vec!["stale".to_string()];
+        depgraph.resolve_into(&vec!["first".to_string()], &mut order).unwrap();
+        assert_eq!(order, vec!["second", "first"]);
+        depgraph.resolve_into(&vec!["second".to_string()], &mut order).unwrap();
+        assert_eq!(order, vec!["second"]);
+        assert!(depgraph.resolve_into(&vec!["third".to_string()], &mut order).is_err());
+        assert!(order.is_empty());
+    }
+
+    #[test]
+    fn tuple_deps() {
+        let mut depgraph = Dependy::new();
+        depgraph.add_dependency(&("first", vec!["deux"], vec![], vec![]));
+        depgraph.add_dependency(&("second", vec![], vec!["third"], vec!["deux"]));
+        depgraph.add_dependency(&("third", vec![], vec![], vec![]));
+
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["first"]).unwrap();
+        assert_eq!(dep_chain, vec!["third", "second", "first"]);
+    }
+
+    #[test]
+    fn resolve_grouped() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("top",
+                                vec!["a-one".to_string(),
+                                     "b-one".to_string(),
+                                     "a-two".to_string(),
+                                     "b-two".to_string()],
+                                vec![],
+                                vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&SimpleDep::new("a-one", vec![], vec![], vec![]));
+        depgraph.add_dependency(&SimpleDep::new("b-one", vec![], vec![], vec![]));
+        depgraph.add_dependency(&SimpleDep::new("a-two", vec![], vec![], vec![]));
+        depgraph.add_dependency(&SimpleDep::new("b-two",
+                                                vec!["a-one".to_string()],
+                                                vec![],
+                                                vec![]));
+
+        let first_char = |name: &String| name.chars().next().unwrap();
+        let dep_chain = depgraph.resolve_grouped(&vec!["top".to_string()], first_char).unwrap();
+        assert_eq!(dep_chain.len(), 5);
+        assert_eq!(dep_chain[4], "top");
+        let groups: Vec<char> = dep_chain[..4].iter().map(first_char).collect();
+        assert_eq!(groups, vec!['a', 'a', 'b', 'b']);
+        assert!(index_of(&dep_chain, &"a-one".to_string()) <
+                index_of(&dep_chain, &"b-two".to_string()));
+        assert_eq!(depgraph.rank_of(&"top".to_string()), Some(4));
+    }
+
+    #[test]
+    fn resolve_best_effort() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "missing".to_string()],
+                                vec!["absent".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec!["first".to_string()], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let roots = vec!["nowhere".to_string(), "first".to_string()];
+        assert!(depgraph.resolve_named_dependencies(&roots).is_err());
+        depgraph.reset_graph();
+        let (order, problems) = depgraph.resolve_best_effort(&roots);
+        assert_eq!(order, vec!["second", "first"]);
+        assert_eq!(problems.len(), 4);
+        match problems[0] {
+            DepError::DependencyNotFound(ref requested) => {
+                assert_eq!(requested, "nowhere")
+            }
+            ref other => panic!("unexpected problem: {:?}", other),
+        }
+        match (&problems[1], &problems[2], &problems[3]) {
+            (DepError::RequirementNotFound { resolved: a, requirement: b, .. },
+             DepError::SuggestionNotFound { resolved: c, suggestion: d, .. },
+             DepError::CircularDependency { resolved: e, target: f, .. }) => {
+                assert_eq!((a.as_str(), b.as_str()), ("first", "missing"));
+                assert_eq!((c.as_str(), d.as_str()), ("first", "absent"));
+                assert_eq!((e.as_str(), f.as_str()), ("second", "first"));
+            }
+            other => panic!("unexpected problems: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rank_of() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        assert_eq!(depgraph.rank_of(&"second".to_string()), Some(0));
+        assert_eq!(depgraph.rank_of(&"deux".to_string()), Some(0));
+        assert_eq!(depgraph.rank_of(&"first".to_string()), Some(1));
+        assert_eq!(depgraph.rank_of(&"third".to_string()), None);
+
+        depgraph.reset_graph();
+        assert_eq!(depgraph.rank_of(&"first".to_string()), None);
+    }
+
+    #[test]
+    fn tie_breaker() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["beta".to_string(), "alpha".to_string(), "gamma".to_string()],
+                                vec![],
+                                vec![]);
+        let alpha = SimpleDep::new("alpha", vec![], vec![], vec![]);
+        let beta = SimpleDep::new("beta", vec![], vec![], vec![]);
+        let gamma = SimpleDep::new("gamma", vec!["beta".to_string()], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&alpha);
+        depgraph.add_dependency(&beta);
+        depgraph.add_dependency(&gamma);
+        let roots = vec!["first".to_string()];
+
+        depgraph.with_tie_breaker(|a: &String, b: &String| a.cmp(b));
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap(),
+                   vec!["alpha", "beta", "gamma", "first"]);
+
+        // Reversing the order still has to keep beta ahead of gamma.
+        depgraph.with_tie_breaker(|a: &String, b: &String| b.cmp(a));
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap(),
+                   vec!["beta", "gamma", "alpha", "first"]);
+    }
+
+    #[test]
+    fn tie_breaker_across_subtrees() {
+        let mut depgraph = Dependy::new();
+        let r = SimpleDep::new("r", vec!["c".to_string(), "b".to_string()], vec![], vec![]);
+        let c = SimpleDep::new("c", vec!["a".to_string()], vec![], vec![]);
+        depgraph.add_dependency(&r);
+        depgraph.add_dependency(&c);
+        for name in &["a", "b", "y", "z"] {
+            depgraph.add_dependency(&SimpleDep::new(name, vec![], vec![], vec![]));
+        }
+        depgraph.with_tie_breaker(|a: &String, b: &String| a.cmp(b));
+
+        // "a" and "b" don't depend on one another, even though "a" is only
+        // reached through "c".
+        assert_eq!(depgraph.resolve_named_dependencies(&vec!["r".to_string()]).unwrap(),
+                   vec!["a", "b", "c", "r"]);
+
+        // Independent roots are ordered too, once they don't follow on.
+        depgraph.with_follows(false);
+        let roots = vec!["z".to_string(), "y".to_string()];
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap(), vec!["y", "z"]);
+    }
+
+    #[test]
+    fn provides() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain.len(), 2);
+        assert_eq!(dep_chain[0], "second");
+        assert_eq!(dep_chain[1], "first");
+        assert_eq!(depgraph.capability_map().len(), 3);
+        assert_eq!(depgraph.capability_map()["second"], "second");
+        assert_eq!(depgraph.capability_map()["deux"], "second");
+    }
+
+    #[test]
+    fn dot_labels() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second",
+                                vec![],
+                                vec![],
+                                vec!["deux".to_string(), "zwei".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        let mut dot = String::new();
+        depgraph.write_dot(&mut dot).unwrap();
+        assert!(dot.contains("label=\"first\""));
+        assert!(dot.contains("label=\"second\\lprovides: deux, zwei\""));
+        assert!(dot.contains("label=\"Requires\""));
+    }
+
+    #[test]
+    fn candidates_for() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("native", vec![], vec![], vec!["backend".to_string()]);
+        let d2 = SimpleDep::new("generic", vec![], vec![], vec!["backend".to_string()]);
+        let d3 = SimpleDep::new("frontend", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let mut candidates = depgraph.candidates_for(&"backend".to_string());
+        candidates.sort();
+        assert_eq!(candidates, vec!["generic", "native"]);
+        assert!(depgraph.candidates_for(&"frontend".to_string()).is_empty());
+    }
+
+    struct PreferredDep {
+        dep: SimpleDep,
+        priority: i32,
+    }
+    impl Dependency<String> for PreferredDep {
+        fn name(&self) -> &String {
+            self.dep.name()
+        }
+        fn requirements(&self) -> &Vec<String> {
+            self.dep.requirements()
+        }
+        fn suggestions(&self) -> &Vec<String> {
+            self.dep.suggestions()
+        }
+        fn provides(&self) -> &Vec<String> {
+            self.dep.provides()
+        }
+        fn provides_priority(&self, _capability: &String) -> i32 {
+            self.priority
+        }
+    }
+
+    #[test]
+    fn provider_priority() {
+        let backend = "backend".to_string();
+        let generic = PreferredDep {
+            dep: SimpleDep::new("generic", vec![], vec![], vec![backend.clone()]),
+            priority: 0,
+        };
+        let fallback = PreferredDep {
+            dep: SimpleDep::new("fallback", vec![], vec![], vec![backend.clone()]),
+            priority: 0,
+        };
+        let native = PreferredDep {
+            dep: SimpleDep::new("native", vec![], vec![], vec![backend.clone()]),
+            priority: 10,
+        };
+
+        let mut depgraph = Dependy::new();
+        depgraph.add_dependency(&generic);
+        depgraph.add_dependency(&fallback);
+        assert_eq!(depgraph.resolve_named_dependencies(&vec![backend.clone()]).unwrap(),
+                   vec!["generic"]);
+
+        depgraph.add_dependency(&native);
+        assert_eq!(depgraph.resolve_named_dependencies(&vec![backend.clone()]).unwrap(),
+                   vec!["native"]);
+    }
+
+    struct PanickyDep {
+        dep: SimpleDep,
+    }
+    impl Dependency<String> for PanickyDep {
+        fn name(&self) -> &String {
+            self.dep.name()
+        }
+        fn requirements(&self) -> &Vec<String> {
+            self.dep.requirements()
+        }
+        fn suggestions(&self) -> &Vec<String> {
+            self.dep.suggestions()
+        }
+        fn provides(&self) -> &Vec<String> {
+            self.dep.provides()
+        }
+        fn provides_priority(&self, _capability: &String) -> i32 {
+            panic!("no priority for you")
+        }
+    }
+
+    #[test]
+    fn add_dependency_is_atomic() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = PanickyDep {
+            dep: SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]),
+        };
+        depgraph.add_dependency(&d1);
+
+        let added = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            depgraph.add_dependency(&d2);
+        }));
+        assert!(added.is_err());
+        depgraph.assert_consistent();
+        assert_eq!(depgraph.missing_roots(&vec!["second".to_string(), "deux".to_string()]),
+                   vec!["second", "deux"]);
+    }
+
+    #[test]
+    fn assert_consistent_catches_corruption() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+        depgraph.assert_consistent();
+
+        let fails = |depgraph: &Dependy<String>| {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                depgraph.assert_consistent();
+            }))
+            .is_err()
+        };
+
+        let mut broken = depgraph.clone();
+        broken.requirements.insert("first".to_string(), vec![]);
+        assert!(fails(&broken));
+
+        let mut broken = depgraph.clone();
+        let first = broken.node_bucket["first"];
+        broken.node_bucket.insert("deux".to_string(), first);
+        assert!(fails(&broken));
+
+        let mut broken = depgraph.clone();
+        broken.provides_map.insert("zwei".to_string(), "third".to_string());
+        assert!(fails(&broken));
+
+        let mut broken = depgraph.clone();
+        broken.ranks.clear();
+        assert!(fails(&broken));
+    }
+
+    #[test]
+    fn incremental_declarations() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        depgraph.add_provides(&"second".to_string(), "deux".to_string()).unwrap();
+        depgraph.add_requirement(&"first".to_string(), "deux".to_string()).unwrap();
+        depgraph.add_suggestion(&"deux".to_string(), "third".to_string()).unwrap();
+        depgraph.assert_consistent();
+        match depgraph.add_requirement(&"fourth".to_string(), "first".to_string()) {
+            Err(DepError::DependencyNotFound(requested)) => {
+                assert_eq!(requested, "fourth")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain, vec!["third", "second", "first"]);
+    }
+
+    #[test]
+    fn name_collides_with_alias() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("deux", vec![], vec![], vec!["second".to_string()]);
+        let d3 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        // "deux" claimed "second" first, so the later node named "second"
+        // is turned away rather than stealing it.
+        assert!(!depgraph.add_dependency(&d3));
+        assert!(!depgraph.add_group("second".to_string(), vec!["first".to_string()]));
+        assert!(!depgraph.dep_map.contains_key("second"));
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain, vec!["deux", "first"]);
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["second".to_string()]).unwrap();
+        assert_eq!(dep_chain, vec!["deux"]);
+        depgraph.assert_consistent();
+    }
+
+    #[test]
+    fn alias_collides_with_name() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("deux", vec![], vec![], vec!["second".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+    }
+
+    #[test]
+    fn provides_own_name() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string()],
+                                vec![],
+                                vec!["first".to_string(), "premier".to_string()]);
+        let d2 = PreferredDep {
+            dep: SimpleDep::new("second",
+                                vec![],
+                                vec![],
+                                vec!["second".to_string(), "second".to_string()]),
+            priority: 0,
+        };
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_provides(&"first".to_string(), "first".to_string()).unwrap();
+        assert_eq!(depgraph.dep_map[&"first".to_string()].provides, vec!["premier"]);
+        assert!(depgraph.dep_map[&"second".to_string()].provides.is_empty());
+        assert!(depgraph.candidates_for(&"first".to_string()).is_empty());
+        depgraph.assert_consistent();
+
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["premier".to_string()])
+            .unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+    }
+
+    #[test]
+    fn own_name_outranks_alias() {
+        // A dependency listing its own name in `provides` must not turn
+        // that name into an alias another dependency could outbid it for.
+        let mut depgraph = Dependy::new();
+        let d1 = PreferredDep {
+            dep: SimpleDep::new("first", vec![], vec![], vec!["first".to_string()]),
+            priority: 1,
+        };
+        let d2 = PreferredDep {
+            dep: SimpleDep::new("impostor", vec![], vec![], vec!["first".to_string()]),
+            priority: 10,
+        };
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        assert_eq!(depgraph.capability_map()["first"], "first");
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["first".to_string()])
+            .unwrap();
+        assert_eq!(dep_chain, vec!["first"]);
+    }
+
+    #[test]
+    fn groups() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec!["both".to_string()], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_group("both".to_string(), vec!["first".to_string(), "second".to_string()]);
+
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["third".to_string()]).unwrap();
+        assert_eq!(dep_chain.len(), 3);
+        assert!(!dep_chain.contains(&"both".to_string()));
+        assert_eq!(dep_chain[2], "third");
+
+        let mut runnable = depgraph.next_runnable();
+        runnable.sort();
+        assert_eq!(runnable, vec!["first", "second"]);
+        depgraph.mark_successful(&"first".to_string());
+        assert_eq!(depgraph.next_runnable(), vec!["second"]);
+        depgraph.mark_failure(&"second".to_string());
+        assert_eq!(depgraph.skipped_dependencies(), vec!["third"]);
+
+        // Parent queries look through the group to its members.
+        let mut parents = depgraph.required_parents_of_named(&"third".to_string());
+        parents.sort();
+        assert_eq!(parents, vec!["first", "second"]);
+        let mut all = depgraph.required_parents_of_all(&["third".to_string()]);
+        all.get_mut("third").unwrap().sort();
+        assert_eq!(all["third"], vec!["first", "second"]);
+        let resolved = depgraph.resolve_with_parents(&vec!["third".to_string()]).unwrap();
+        let third = resolved.iter().find(|(name, _)| name == "third").unwrap();
+        assert!(!third.1.contains(&"both".to_string()));
+        assert_eq!(third.1.len(), 2);
+
+        // A group isn't the same as a dependency with the same members.
+        let mut plain = Dependy::new();
+        plain.add_dependency(&d1);
+        plain.add_dependency(&d2);
+        plain.add_dependency(&d3);
+        plain.add_dependency(&SimpleDep::new("both",
+                                             vec!["first".to_string(), "second".to_string()],
+                                             vec![],
+                                             vec![]));
+        plain.resolve_named_dependencies(&vec!["third".to_string()]).unwrap();
+        assert!(plain != depgraph);
+    }
+
+    #[test]
+    fn resolve_by_tags() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string()],
+                                vec!["third".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec!["helper".to_string()], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
+        let d5 = SimpleDep::new("helper", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.add_dependency(&d5);
+        for name in &["first", "second", "third", "helper"] {
+            depgraph.add_tag(&name.to_string(), "ci".to_string());
+        }
+        depgraph.add_tag(&"third".to_string(), "slow".to_string());
+
+        let ci = vec!["ci".to_string()];
+        let slow = vec!["slow".to_string()];
+        let roots = vec!["fourth".to_string(), "first".to_string()];
+        let dep_chain = depgraph.resolve_by_tags(&roots, &ci, &slow).unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+
+        // Edges left behind by an unfiltered resolve mustn't pull in the
+        // requirements of a filtered-out dependency.
+        depgraph.resolve_named_dependencies(&roots).unwrap();
+        let dep_chain = depgraph.resolve_by_tags(&roots, &ci, &slow).unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+
+        depgraph.add_tag(&"second".to_string(), "slow".to_string());
+        match depgraph.resolve_by_tags(&roots, &ci, &slow) {
+            Err(DepError::RequirementFiltered { resolved: name, requirement: req, .. }) => {
+                assert_eq!((name.as_str(), req.as_str()), ("first", "second"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Tags change what gets resolved, so they're part of equality.
+        let mut retagged = depgraph.clone();
+        assert!(retagged == depgraph);
+        retagged.add_tag(&"fourth".to_string(), "slow".to_string());
+        assert!(retagged != depgraph);
+    }
+
+    #[test]
+    fn max_iterations() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let roots = vec!["first".to_string()];
+        depgraph.with_max_iterations(2);
+        match depgraph.resolve_named_dependencies(&roots) {
+            Err(DepError::ResolutionLimitExceeded(2)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        depgraph.with_max_iterations(3);
+        assert_eq!(depgraph.resolve_named_dependencies(&roots).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn follows() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1, d2, d3]).unwrap();
+        assert_eq!(dep_chain.len(), 3);
+        assert_eq!(dep_chain[0], "first");
+        assert_eq!(dep_chain[1], "second");
+        assert_eq!(dep_chain[2], "third");
+    }
+
+    #[test]
+    fn without_follows() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.with_follows(false);
+
+        depgraph.resolve_dependencies(vec![d1, d2]).unwrap();
+        for edge in depgraph.graph().raw_edges() {
+            assert!(edge.weight != DepEdge::Follows);
+        }
+    }
+
+    #[test]
+    fn depends_and_follows() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["third".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let dep_chain = depgraph.resolve_dependencies(vec![d1, d3, d2]).unwrap();
+        assert_eq!(dep_chain.len(), 3);
+        assert_eq!(dep_chain[0], "third");
+        assert_eq!(dep_chain[1], "first");
+        assert_eq!(dep_chain[2], "second");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    struct SimpleDep {
-        name: String,
-        requirements: Vec<String>,
-        suggestions: Vec<String>,
-        provides: Vec<String>,
+    #[test]
+    fn runnable_and_skipped() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        assert_eq!(depgraph.next_runnable(), vec!["third"]);
+        depgraph.mark_successful(&"third".to_string());
+        assert_eq!(depgraph.next_runnable(), vec!["second"]);
+        depgraph.mark_failure(&"second".to_string());
+        assert!(depgraph.next_runnable().is_empty());
+        assert_eq!(depgraph.skipped_dependencies(), vec!["first"]);
+        assert_eq!(depgraph.resolution_state(),
+                   ResolutionState::Blocked(vec!["first".to_string()]));
+
+        depgraph.reset_results();
+        depgraph.mark_successful(&"third".to_string());
+        depgraph.mark_successful(&"second".to_string());
+        assert_eq!(depgraph.resolution_state(),
+                   ResolutionState::Ready(vec!["first".to_string()]));
+        depgraph.mark_successful(&"first".to_string());
+        assert_eq!(depgraph.resolution_state(), ResolutionState::Complete);
     }
-    impl SimpleDep {
-        pub fn new(name: &str,
-                   requirements: Vec<String>,
-                   suggestions: Vec<String>,
-                   provides: Vec<String>)
-                   -> SimpleDep {
-            SimpleDep {
-                name: name.to_owned(),
-                requirements: requirements,
-                suggestions: suggestions,
-                provides: provides,
+
+    #[test]
+    fn suggestion_of_skipped() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["first".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec!["second".to_string()], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_dependencies(vec![d3]).unwrap();
+
+        // A suggestion that will never run doesn't hold "third" up, so
+        // this isn't a deadlock.
+        depgraph.mark_failure(&"first".to_string());
+        assert_eq!(depgraph.resolution_state(),
+                   ResolutionState::Ready(vec!["third".to_string()]));
+        depgraph.mark_successful(&"third".to_string());
+        assert_eq!(depgraph.resolution_state(),
+                   ResolutionState::Blocked(vec!["second".to_string()]));
+    }
+
+    #[test]
+    fn resolve_remaining() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "third".to_string()],
+                                vec![],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec!["fourth".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec!["fifth".to_string()], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
+        let d5 = SimpleDep::new("fifth", vec![], vec![], vec!["funf".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.add_dependency(&d5);
+
+        let completed = vec!["second".to_string(), "funf".to_string()].into_iter().collect();
+        let remaining = depgraph.resolve_remaining(&vec!["first".to_string()], &completed)
+            .unwrap();
+        assert_eq!(remaining, vec!["third", "first"]);
+        assert_eq!(depgraph.next_runnable(), vec!["third"]);
+    }
+
+    #[test]
+    fn try_mark() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        depgraph.try_mark_successful(&"deux".to_string()).unwrap();
+        assert_eq!(depgraph.next_runnable(), vec!["first"]);
+        match depgraph.try_mark_failure(&"frist".to_string()) {
+            Err(DepError::DependencyNotFound(requested)) => {
+                assert_eq!(requested, "frist")
             }
+            other => panic!("unexpected result: {:?}", other),
         }
+        assert_eq!(depgraph.reset_results(), 1);
     }
-    impl Dependency<String> for SimpleDep {
-        fn name(&self) -> &String {
-            &self.name
-        }
-        fn requirements(&self) -> &Vec<String> {
-            &self.requirements
-        }
-        fn suggestions(&self) -> &Vec<String> {
-            &self.suggestions
-        }
-        fn provides(&self) -> &Vec<String> {
-            &self.provides
-        }
+
+    #[test]
+    fn clear_result() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        depgraph.mark_successful(&"second".to_string());
+        depgraph.mark_failure(&"first".to_string());
+        assert!(depgraph.next_runnable().is_empty());
+        assert_eq!(depgraph.clear_result(&"first".to_string()), Some(false));
+        assert_eq!(depgraph.clear_result(&"first".to_string()), None);
+        assert_eq!(depgraph.next_runnable(), vec!["first"]);
+
+        assert_eq!(depgraph.clear_result(&"deux".to_string()), Some(true));
+        assert_eq!(depgraph.next_runnable(), vec!["second"]);
+        depgraph.mark_successful(&"second".to_string());
+        assert_eq!(depgraph.reset_results(), 1);
+        assert_eq!(depgraph.reset_results(), 0);
     }
+
     #[test]
-    fn single_dep() {
+    fn relationship() {
         let mut depgraph = Dependy::new();
-        let d1 = SimpleDep::new("single", vec![], vec![], vec![]);
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second",
+                                vec![],
+                                vec!["third".to_string()],
+                                vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
         depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_dependencies(vec![d3, d1]).unwrap();
+
+        let rel = |from: &str, to: &str| depgraph.relationship(&from.to_string(), &to.to_string());
+        assert_eq!(rel("deux", "first"), Some(DepEdge::Requires));
+        assert_eq!(rel("third", "second"), Some(DepEdge::Suggests));
+        assert_eq!(rel("third", "first"), Some(DepEdge::Follows));
+        assert_eq!(rel("first", "second"), None);
+        assert_eq!(rel("first", "fourth"), None);
+    }
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
-        assert_eq!(dep_chain.len(), 1);
-        assert_eq!(dep_chain[0], "single");
+    #[test]
+    fn node_kinds() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec!["third".to_string()], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.resolve_dependencies(vec![d1, d4]).unwrap();
+
+        assert_eq!(depgraph.node_kinds(),
+                   vec![("third".to_string(), NodeKind::Leaf),
+                        ("second".to_string(), NodeKind::Internal),
+                        ("first".to_string(), NodeKind::Root),
+                        ("fourth".to_string(), NodeKind::Both)]);
     }
 
     #[test]
-    fn two_deps() {
+    fn requirements_within() {
         let mut depgraph = Dependy::new();
         let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
-        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec!["drei".to_string()]);
+        let d4 = SimpleDep::new("fourth",
+                                vec!["drei".to_string(), "first".to_string()],
+                                vec![],
+                                vec![]);
         depgraph.add_dependency(&d1);
         depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+
+        let fourth = "fourth".to_string();
+        assert_eq!(depgraph.requirements_within(&fourth, 0),
+                   vec![("third".to_string(), 0), ("first".to_string(), 0)]);
+        assert_eq!(depgraph.requirements_within(&fourth, 1),
+                   vec![("third".to_string(), 0),
+                        ("first".to_string(), 0),
+                        ("second".to_string(), 1)]);
+        assert!(depgraph.requirements_within(&"missing".to_string(), 3).is_empty());
+    }
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
-        assert_eq!(dep_chain.len(), 2);
-        assert_eq!(dep_chain[0], "second");
-        assert_eq!(dep_chain[1], "first");
+    #[test]
+    fn requires_transitively() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["drei".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec!["drei".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let first = "first".to_string();
+        let third = "third".to_string();
+        assert!(depgraph.requires_transitively(&first, &third));
+        assert!(depgraph.requires_transitively(&first, &"drei".to_string()));
+        assert!(!depgraph.requires_transitively(&third, &first));
+        assert!(!depgraph.requires_transitively(&first, &"missing".to_string()));
     }
 
+    #[cfg(feature = "serde")]
     #[test]
-    fn three_deps() {
+    fn resolve_with_events() {
         let mut depgraph = Dependy::new();
         let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+
+        let mut events = vec![];
+        depgraph.resolve_with_events(&vec!["first".to_string()], &mut events).unwrap();
+        let events = String::from_utf8(events).unwrap();
+        let lines: Vec<&str> = events.lines().collect();
+        assert_eq!(lines,
+                   vec![r#"{"edge":"Requires","event":"edge_added","from":"second","to":"first"}"#,
+                        r#"{"event":"visited","name":"second"}"#,
+                        r#"{"event":"visited","name":"first"}"#]);
+    }
+
+    #[test]
+    fn structural_equality() {
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        let first = "first".to_string();
+        let third = "third".to_string();
+
+        let mut a = Dependy::new();
+        a.add_dependency(&d1);
+        a.add_dependency(&d2);
+        a.add_dependency(&d3);
+        let mut b = Dependy::new();
+        b.add_dependency(&d3);
+        b.add_dependency(&d2);
+        b.add_dependency(&d1);
+        assert!(a == b);
+
+        a.resolve_named_dependencies(&vec![first.clone()]).unwrap();
+        assert!(a != b);
+        b.resolve_named_dependencies(&vec![first.clone()]).unwrap();
+        assert!(a == b);
+
+        a.resolve_named_dependencies(&vec![first.clone(), third.clone()]).unwrap();
+        assert!(a != b);
+    }
+
+    #[test]
+    fn write_order_csv() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second, again".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second, again",
+                                vec![],
+                                vec![],
+                                vec!["deux".to_string(), "zwei".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        let order = depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        let mut csv = vec![];
+        depgraph.write_order_csv(&order, &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(),
+                   "position,name,direct_requirements,provides\r\n\
+                    0,\"second, again\",,deux;zwei\r\n\
+                    1,first,\"second, again\",\r\n");
+    }
+
+    #[test]
+    fn required_parents_of_all() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "third".to_string()],
+                                vec!["fourth".to_string()],
+                                vec![]);
         let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
         let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
         depgraph.add_dependency(&d1);
         depgraph.add_dependency(&d2);
         depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        let order = depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        let mut names = order.clone();
+        names.push("missing".to_string());
+        let all = depgraph.required_parents_of_all(&names);
+        assert_eq!(all.len(), order.len());
+        for name in &order {
+            let one: Vec<String> = depgraph.required_parents_of_named(name)
+                .into_iter()
+                .cloned()
+                .collect();
+            assert_eq!(all[name], one);
+        }
+    }
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
-        assert_eq!(dep_chain.len(), 3);
-        assert_eq!(dep_chain[0], "third");
-        assert_eq!(dep_chain[1], "second");
-        assert_eq!(dep_chain[2], "first");
+    #[test]
+    fn duplicate_edges() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "deux".to_string()],
+                                vec!["second".to_string(), "third".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+        assert_eq!(depgraph.duplicate_edges(),
+                   &[("second".to_string(), "first".to_string(), DepEdge::Requires),
+                     ("second".to_string(), "first".to_string(), DepEdge::Suggests)][..]);
+
+        // Edges left over from the previous resolution aren't duplicates.
+        depgraph.resolve_named_dependencies(&vec!["third".to_string()]).unwrap();
+        assert!(depgraph.duplicate_edges().is_empty());
     }
 
     #[test]
-    fn provides() {
+    fn edge_counts() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["third".to_string()],
+                                vec!["second".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec!["drei".to_string()]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        assert_eq!(depgraph.requirement_count(&"first".to_string()), 1);
+        assert_eq!(depgraph.dependent_count(&"first".to_string()), 0);
+        assert_eq!(depgraph.requirement_count(&"drei".to_string()), 0);
+        assert_eq!(depgraph.dependent_count(&"drei".to_string()), 2);
+        assert_eq!(depgraph.dependent_count(&"missing".to_string()), 0);
+    }
+
+    #[test]
+    fn resolve_with_parents() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string()],
+                                vec!["third".to_string()],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+
+        let resolved = depgraph.resolve_with_parents(&vec!["first".to_string()]).unwrap();
+        let first = resolved.iter().find(|(name, _)| name == "first").unwrap();
+        assert_eq!(first.1, vec!["second"]);
+        let second = resolved.iter().find(|(name, _)| name == "second").unwrap();
+        assert!(second.1.is_empty());
+    }
+
+    #[test]
+    fn resolve_annotated() {
         let mut depgraph = Dependy::new();
         let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
-        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d2 = SimpleDep::new("second",
+                                vec![],
+                                vec![],
+                                vec!["deux".to_string(), "zwei".to_string()]);
         depgraph.add_dependency(&d1);
         depgraph.add_dependency(&d2);
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1]).unwrap();
-        assert_eq!(dep_chain.len(), 2);
-        assert_eq!(dep_chain[0], "second");
-        assert_eq!(dep_chain[1], "first");
+        let resolved = depgraph.resolve_annotated(&vec!["first".to_string()]).unwrap();
+        assert_eq!(resolved,
+                   vec![("second".to_string(), vec!["deux".to_string(), "zwei".to_string()]),
+                        ("first".to_string(), vec![])]);
     }
 
+    #[test]
+    fn write_script() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        let order = depgraph.resolve_dependencies(vec![d1]).unwrap();
+
+        let mut script = vec![];
+        depgraph.write_script(&order, |name| format!("make {}", name), &mut script).unwrap();
+        assert_eq!(String::from_utf8(script).unwrap(),
+                   "#!/bin/sh\nset -e\nmake second\nmake first\n");
+    }
 
     #[test]
-    fn follows() {
+    fn write_tree() {
         let mut depgraph = Dependy::new();
-        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
-        let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "drei".to_string()],
+                                vec![],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third",
+                                vec!["fourth".to_string()],
+                                vec![],
+                                vec!["drei".to_string()]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+
+        let mut tree = vec![];
+        depgraph.write_tree(&"first".to_string(), &mut tree).unwrap();
+        assert_eq!(String::from_utf8(tree).unwrap(),
+                   "first\n\
+                    ├── second\n\
+                    │   └── third\n\
+                    │       └── fourth\n\
+                    └── third (*)\n");
+    }
+
+    #[test]
+    fn diamonds() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first",
+                                vec!["second".to_string(), "third".to_string()],
+                                vec![],
+                                vec![]);
+        let d2 = SimpleDep::new("second", vec!["fourth".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec!["vier".to_string()], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth",
+                                vec!["fifth".to_string()],
+                                vec![],
+                                vec!["vier".to_string()]);
+        let d5 = SimpleDep::new("fifth", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.add_dependency(&d5);
+
+        assert_eq!(depgraph.diamonds(&"first".to_string()), vec!["fourth", "fifth"]);
+        assert!(depgraph.diamonds(&"second".to_string()).is_empty());
+        assert!(depgraph.diamonds(&"sixth".to_string()).is_empty());
+    }
+
+    #[test]
+    fn common_requirements() {
+        let mut depgraph = Dependy::new();
+        let app = SimpleDep::new("app", vec!["lib".to_string()], vec![], vec![]);
+        let tests = SimpleDep::new("tests",
+                                   vec!["lib".to_string(), "fixtures".to_string()],
+                                   vec![],
+                                   vec![]);
+        let lib = SimpleDep::new("lib", vec!["toolchain".to_string()], vec![], vec![]);
+        let fixtures = SimpleDep::new("fixtures", vec![], vec![], vec![]);
+        let toolchain = SimpleDep::new("toolchain", vec![], vec![], vec![]);
+        depgraph.add_dependency(&app);
+        depgraph.add_dependency(&tests);
+        depgraph.add_dependency(&lib);
+        depgraph.add_dependency(&fixtures);
+        depgraph.add_dependency(&toolchain);
+
+        let app = "app".to_string();
+        let tests = "tests".to_string();
+        assert_eq!(depgraph.common_requirements(&app, &tests), vec!["lib", "toolchain"]);
+        assert_eq!(depgraph.closest_common_requirements(&app, &tests), vec!["lib"]);
+    }
+
+    #[test]
+    fn retain_edges() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec!["third".to_string()], vec![]);
         let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
         depgraph.add_dependency(&d1);
         depgraph.add_dependency(&d2);
         depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.resolve_dependencies(vec![d4, d1]).unwrap();
+        assert_eq!(depgraph.graph().edge_count(), 3);
+
+        depgraph.retain_edges(|edge| edge != DepEdge::Follows);
+        let edges: Vec<DepEdge> = depgraph.graph().raw_edges().iter().map(|e| e.weight).collect();
+        assert_eq!(edges.len(), 2);
+        assert!(edges.contains(&DepEdge::Requires) && edges.contains(&DepEdge::Suggests));
+
+        depgraph.retain_edges(|edge| edge == DepEdge::Requires);
+        assert_eq!(depgraph.required_parents_of_named(&"first".to_string()), vec!["second"]);
+        assert_eq!(depgraph.graph().edge_count(), 1);
+    }
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1, d2, d3]).unwrap();
-        assert_eq!(dep_chain.len(), 3);
-        assert_eq!(dep_chain[0], "first");
-        assert_eq!(dep_chain[1], "second");
-        assert_eq!(dep_chain[2], "third");
+    #[test]
+    fn snapshot() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["second".to_string()], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec!["third".to_string()], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        depgraph.add_dependency(&d3);
+        depgraph.resolve_named_dependencies(&vec!["second".to_string()]).unwrap();
+        let before = depgraph.clone();
+
+        let snapshot = depgraph.snapshot();
+        depgraph.resolve_named_dependencies(&vec!["first".to_string()]).unwrap();
+        assert_eq!(depgraph.graph().edge_count(), 2);
+        depgraph.restore(snapshot);
+        assert!(depgraph == before);
+        assert_eq!(depgraph.rank_of(&"second".to_string()), Some(1));
+        assert_eq!(depgraph.rank_of(&"first".to_string()), None);
+
+        let snapshot = depgraph.snapshot();
+        depgraph.reset_graph();
+        let restored = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            depgraph.restore(snapshot);
+        }));
+        assert!(restored.is_err());
     }
 
     #[test]
-    fn depends_and_follows() {
+    fn reset_graph() {
         let mut depgraph = Dependy::new();
-        let d1 = SimpleDep::new("first", vec!["third".to_string()], vec![], vec![]);
+        let d1 = SimpleDep::new("first", vec![], vec![], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        let first = "first".to_string();
+        let second = "second".to_string();
+
+        depgraph.resolve_named_dependencies(&vec![first.clone(), second.clone()]).unwrap();
+
+        // The "Follows" edge from the first resolve would pin the old order.
+        depgraph.reset_graph();
+        let dep_chain = depgraph.resolve_named_dependencies(&vec![second.clone(), first.clone()])
+            .unwrap();
+        assert_eq!(dep_chain, vec![second, first]);
+    }
+
+    #[test]
+    fn prune_unreachable() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec!["deux".to_string()], vec![], vec![]);
         let d2 = SimpleDep::new("second", vec![], vec![], vec!["deux".to_string()]);
-        let d3 = SimpleDep::new("third", vec![], vec![], vec![]);
+        let d3 = SimpleDep::new("third", vec!["first".to_string()], vec![], vec![]);
+        let d4 = SimpleDep::new("fourth", vec![], vec![], vec![]);
         depgraph.add_dependency(&d1);
         depgraph.add_dependency(&d2);
         depgraph.add_dependency(&d3);
+        depgraph.add_dependency(&d4);
+        depgraph.resolve_named_dependencies(&vec!["third".to_string(), "fourth".to_string()])
+            .unwrap();
 
-        let dep_chain = depgraph.resolve_dependencies(vec![d1, d3, d2]).unwrap();
-        assert_eq!(dep_chain.len(), 3);
-        assert_eq!(dep_chain[0], "third");
-        assert_eq!(dep_chain[1], "first");
-        assert_eq!(dep_chain[2], "second");
+        depgraph.prune_unreachable(&vec!["first".to_string()]);
+        assert_eq!(depgraph.graph().node_count(), 2);
+        assert_eq!(depgraph.graph().edge_count(), 1);
+        assert_eq!(depgraph.missing_roots(&vec!["deux".to_string(), "third".to_string()]),
+                   vec!["third"]);
+
+        let dep_chain = depgraph.resolve_named_dependencies(&vec!["first".to_string()]).unwrap();
+        assert_eq!(dep_chain, vec!["second", "first"]);
+        depgraph.assert_consistent();
+    }
+
+    #[test]
+    fn promoted_suggestions() {
+        let mut depgraph = Dependy::new();
+        let d1 = SimpleDep::new("first", vec![], vec!["second".to_string()], vec![]);
+        let d2 = SimpleDep::new("second", vec![], vec![], vec![]);
+        depgraph.add_dependency(&d1);
+        depgraph.add_dependency(&d2);
+        let roots = vec!["first".to_string()];
+
+        depgraph.resolve_named_dependencies(&roots).unwrap();
+        depgraph.mark_failure(&"second".to_string());
+        assert_eq!(depgraph.next_runnable(), vec!["first"]);
+
+        depgraph.resolve_promoting_suggestions(&roots).unwrap();
+        assert!(depgraph.next_runnable().is_empty());
+        assert_eq!(depgraph.skipped_dependencies(), vec!["first"]);
     }
 
     #[test]
@@ -529,11 +3552,10 @@ mod tests {
                                        program_app])
             .unwrap();
 
-        {
-            let mut dotfile = File::create("./depgraph.dot").expect("Unable to open depgraph.dot");
-            depgraph.save_dot(&mut dotfile).expect("Unable to write dotfile");
-        }
+        let mut dotfile = File::create("./depgraph.dot").expect("Unable to open depgraph.dot");
+        depgraph.save_dot(&mut dotfile).expect("Unable to write dotfile");
 
+        depgraph.assert_consistent();
         println!("Resolved dep chain: {:?}", dep_chain);
         for depname in &dep_chain {
             validate_parents_present(&depgraph, &dep_chain, &depname);